@@ -5,13 +5,30 @@ const ref = require('ref-napi');
 
 const mylib = ffi.Library('./target/debug/libmylib.dylib', {
     'init': ['void', []],
-    add_job: ['void', ['string', 'pointer']]
+    init_with_config: ['void', ['uint32', 'uint32']],
+    add_job: ['void', ['string', 'pointer']],
+    cancel_job: ['bool', ['string']],
+    pause_job: ['bool', ['string']],
+    resume_job: ['bool', ['string']],
+    // returns a string that has to be released with free_string, see jobStatus
+    job_status: ['pointer', ['string']],
+    free_string: ['void', ['pointer']],
 });
 
 mylib.init();
 
-function updateCallback({ status, nesting_solution, error }) {
-    console.log('node update', status, nesting_solution, error);
+function jobStatus(nestingJobUlid) {
+    const statusPtr = mylib.job_status(nestingJobUlid);
+    if (statusPtr.isNull()) {
+        return null;
+    }
+    const status = JSON.parse(ref.readCString(statusPtr, 0));
+    mylib.free_string(statusPtr);
+    return status;
+}
+
+function updateCallback({ status, queue_position, nesting_solution, error, nfp_cache_stats }) {
+    console.log('node update', status, queue_position, nesting_solution, error, nfp_cache_stats);
     console.log(nesting_solution?.placements_and_location)
 }
 
@@ -22,7 +39,8 @@ const updateCallbackPtr = ffi.Callback('void',
         updateCallback(jobUpdate);
     });
 
-// Make an extra reference to the callback pointer to avoid GC, a common practice in
+// The update string is freed when the callback returns, ffi-napi copies it into a JS string before
+// that. Make an extra reference to the callback pointer to avoid GC, a common practice in
 // ffi-napi: https://www.sobyte.net/post/2022-02/communicate-with-cpp-code-in-node/
 process.on('exit', function () {
     updateCallbackPtr
@@ -36,26 +54,30 @@ setInterval(function () {
 
 const input = JSON.stringify({
     nesting_job_ulid: '01EYQZJZJZJZJZJZJZJZJZJZJZ',
-    tool_diameter: 19,
-    timeout: 60 * 60 * 1000, // 1 hour
+    tool_diameter: 6,
+    timeout: 10 * 1000, // 10 seconds
     parts: [
         {
             quantity: 5,
-            contour: [{ x: 0, y: 0 }, { x: 1, y: 1 }, { x: 1, y: 1 }],
-            rotations: [0, 180]
+            contour: [{ x: 0, y: 0 }, { x: 300, y: 0 }, { x: 300, y: 200 }, { x: 0, y: 200 }],
+            rotations: [0, 90]
         },
         {
             quantity: 5,
-            contour: [{ x: 0, y: 0 }, { x: 1, y: 1 }, { x: 1, y: 1 }],
-            rotations: [0, 180]
+            contour: [
+                { x: 0, y: 0 }, { x: 400, y: 0 }, { x: 400, y: 100 },
+                { x: 100, y: 100 }, { x: 100, y: 300 }, { x: 0, y: 300 }
+            ],
+            rotations: [0, 90, 180, 270]
         },
     ],
     sheets: [
-        { length: 10.0, width: 20.0, cost: 5.0 },
-        { length: 15.0, width: 25.0, cost: 8.0 },
-        { length: 30.0, width: 40.0, cost: 12.0 }
+        { length: 1000.0, width: 500.0, cost: 5.0 },
+        { length: 1500.0, width: 1000.0, cost: 8.0 },
+        { length: 2500.0, width: 1250.0, cost: 12.0 }
     ],
 });
 
 console.log('input', input)
 mylib.add_job(input, updateCallbackPtr);
+console.log('status', jobStatus('01EYQZJZJZJZJZJZJZJZJZJZJZ'));
@@ -0,0 +1,283 @@
+// crossover operators that combine two placement sequences into two children. A gene is a
+// placement, identified by its part_index and nth_part, so the children have to be permutations of
+// the genes of their parents. Genes keep the angle and mirroring of the parent they're taken from.
+
+use std::collections::HashMap;
+
+use rand::{Rng, RngCore};
+
+use crate::job::{self, Placement};
+
+pub trait Crossover {
+    fn cross(
+        &self,
+        male: &[Placement],
+        female: &[Placement],
+        rng: &mut dyn RngCore,
+    ) -> (Vec<Placement>, Vec<Placement>);
+}
+
+pub fn operator(crossover: job::Crossover) -> &'static (dyn Crossover + Sync) {
+    match crossover {
+        job::Crossover::OnePoint => &OnePoint,
+        job::Crossover::Order => &Order,
+        job::Crossover::PartiallyMapped => &PartiallyMapped,
+        job::Crossover::Cycle => &Cycle,
+    }
+}
+
+// the genes before the cut of one parent, followed by the remaining genes in the order of the
+// other parent
+pub struct OnePoint;
+
+// the genes between two cuts of one parent stay where they are, the remaining genes are filled in
+// after the second cut in the order of the other parent (OX)
+pub struct Order;
+
+// the genes between two cuts of one parent stay where they are, the other genes stay at their
+// position in the other parent, unless that position is taken. Then they move to the position
+// that is mapped to it through the segment (PMX).
+pub struct PartiallyMapped;
+
+// every gene keeps the position it has in one of the parents. The positions are split into cycles
+// that are taken alternately from either parent.
+pub struct Cycle;
+
+type Gene = (usize, u32);
+
+fn gene(placement: &Placement) -> Gene {
+    (placement.part_index, placement.nth_part)
+}
+
+fn positions(placements: &[Placement]) -> HashMap<Gene, usize> {
+    placements
+        .iter()
+        .enumerate()
+        .map(|(i, placement)| (gene(placement), i))
+        .collect()
+}
+
+fn two_cuts(len: usize, rng: &mut dyn RngCore) -> (usize, usize) {
+    let (a, b) = (rng.gen_range(0..=len), rng.gen_range(0..=len));
+    (a.min(b), a.max(b))
+}
+
+impl Crossover for OnePoint {
+    fn cross(
+        &self,
+        male: &[Placement],
+        female: &[Placement],
+        rng: &mut dyn RngCore,
+    ) -> (Vec<Placement>, Vec<Placement>) {
+        // don't cut too close to the ends, so both parents contribute
+        let ignore_count = male.len() / 10;
+        let cut = rng.gen_range(ignore_count..=male.len() - ignore_count);
+
+        let child = |first: &[Placement], second: &[Placement]| {
+            let head = &first[..cut];
+            let taken = positions(head);
+            head.iter()
+                .chain(second.iter().filter(|p| !taken.contains_key(&gene(p))))
+                .copied()
+                .collect()
+        };
+        (child(male, female), child(female, male))
+    }
+}
+
+impl Crossover for Order {
+    fn cross(
+        &self,
+        male: &[Placement],
+        female: &[Placement],
+        rng: &mut dyn RngCore,
+    ) -> (Vec<Placement>, Vec<Placement>) {
+        let len = male.len();
+        let (a, b) = two_cuts(len, rng);
+
+        let child = |first: &[Placement], second: &[Placement]| {
+            let taken = positions(&first[a..b]);
+            let mut rest = (0..len)
+                .map(|i| second[(b + i) % len])
+                .filter(|p| !taken.contains_key(&gene(p)));
+            let mut child = first.to_vec();
+            for i in (0..len - (b - a)).map(|i| (b + i) % len) {
+                child[i] = rest.next().unwrap();
+            }
+            child
+        };
+        (child(male, female), child(female, male))
+    }
+}
+
+impl Crossover for PartiallyMapped {
+    fn cross(
+        &self,
+        male: &[Placement],
+        female: &[Placement],
+        rng: &mut dyn RngCore,
+    ) -> (Vec<Placement>, Vec<Placement>) {
+        let (a, b) = two_cuts(male.len(), rng);
+
+        let child = |first: &[Placement], second: &[Placement]| {
+            let mut child: Vec<Option<Placement>> = vec![None; first.len()];
+            for i in a..b {
+                child[i] = Some(first[i]);
+            }
+            let taken = positions(&first[a..b]);
+            let second_positions = positions(second);
+
+            // genes of the segment of second that aren't in the segment of first are placed at the
+            // position where the gene of first that took their place is in second
+            for (i, placement) in second.iter().enumerate().take(b).skip(a) {
+                if taken.contains_key(&gene(placement)) {
+                    continue;
+                }
+                let mut j = i;
+                while (a..b).contains(&j) {
+                    j = second_positions[&gene(&first[j])];
+                }
+                child[j] = Some(*placement);
+            }
+
+            child
+                .into_iter()
+                .zip(second)
+                .map(|(placement, second)| placement.unwrap_or(*second))
+                .collect()
+        };
+        (child(male, female), child(female, male))
+    }
+}
+
+impl Crossover for Cycle {
+    fn cross(
+        &self,
+        male: &[Placement],
+        female: &[Placement],
+        _rng: &mut dyn RngCore,
+    ) -> (Vec<Placement>, Vec<Placement>) {
+        let male_positions = positions(male);
+        let mut cycle_of = vec![None; male.len()];
+        let mut cycle = 0;
+        for start in 0..male.len() {
+            if cycle_of[start].is_some() {
+                continue;
+            }
+            let mut i = start;
+            while cycle_of[i].is_none() {
+                cycle_of[i] = Some(cycle);
+                i = male_positions[&gene(&female[i])];
+            }
+            cycle += 1;
+        }
+
+        cycle_of
+            .into_iter()
+            .enumerate()
+            .map(|(i, cycle)| match cycle {
+                Some(cycle) if cycle % 2 == 1 => (female[i], male[i]),
+                _ => (male[i], female[i]),
+            })
+            .unzip()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom;
+    use rand::SeedableRng;
+    use std::collections::HashSet;
+
+    // a random permutation of the placements of 3 parts with random angles
+    fn parent(len: usize, rng: &mut StdRng) -> Vec<Placement> {
+        let mut placements = (0..len)
+            .map(|i| Placement {
+                part_index: i % 3,
+                nth_part: (i / 3) as u32,
+                angle: [0., 90., 180.][rng.gen_range(0..3)],
+                mirrored: rng.gen(),
+            })
+            .collect::<Vec<_>>();
+        placements.shuffle(rng);
+        placements
+    }
+
+    fn genes(placements: &[Placement]) -> HashSet<Gene> {
+        placements.iter().map(gene).collect()
+    }
+
+    #[test]
+    fn children_are_permutations_of_their_parents() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for crossover in [
+            job::Crossover::OnePoint,
+            job::Crossover::Order,
+            job::Crossover::PartiallyMapped,
+            job::Crossover::Cycle,
+        ] {
+            for _ in 0..200 {
+                let len = rng.gen_range(0..20);
+                let (male, female) = (parent(len, &mut rng), parent(len, &mut rng));
+                let (child1, child2) = operator(crossover).cross(&male, &female, &mut rng);
+
+                for child in [&child1, &child2] {
+                    assert_eq!(child.len(), len);
+                    assert_eq!(genes(child), genes(&male));
+                    // every gene comes from one of the parents, with its angle and mirroring
+                    for placement in child {
+                        let from_parent = |parent: &[Placement]| {
+                            parent.iter().any(|p| {
+                                gene(p) == gene(placement)
+                                    && p.angle == placement.angle
+                                    && p.mirrored == placement.mirrored
+                            })
+                        };
+                        assert!(from_parent(&male) || from_parent(&female));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn one_point_takes_the_rest_from_the_other_parent() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let (male, female) = (parent(12, &mut rng), parent(12, &mut rng));
+        let child = |first: &[Placement], second: &[Placement], cut: usize| {
+            let head = positions(&first[..cut]);
+            let rest = second.iter().filter(|p| !head.contains_key(&gene(p)));
+            first[..cut]
+                .iter()
+                .chain(rest)
+                .map(gene)
+                .collect::<Vec<_>>()
+        };
+        for _ in 0..20 {
+            let (child1, child2) = OnePoint.cross(&male, &female, &mut rng);
+            let (child1, child2) = (
+                child1.iter().map(gene).collect::<Vec<_>>(),
+                child2.iter().map(gene).collect::<Vec<_>>(),
+            );
+            assert!((0..=12).any(|cut| {
+                child(&male, &female, cut) == child1 && child(&female, &male, cut) == child2
+            }));
+        }
+    }
+
+    #[test]
+    fn cycle_keeps_the_positions_of_the_parents() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let (male, female) = (parent(15, &mut rng), parent(15, &mut rng));
+        let (child1, child2) = Cycle.cross(&male, &female, &mut rng);
+        for i in 0..15 {
+            let at = [gene(&male[i]), gene(&female[i])];
+            assert!(at.contains(&gene(&child1[i])) && at.contains(&gene(&child2[i])));
+        }
+        // the first cycle comes from the male
+        assert_eq!(gene(&child1[0]), gene(&male[0]));
+        assert_eq!(gene(&child2[0]), gene(&female[0]));
+    }
+}
@@ -1,71 +1,105 @@
-use rand::{random, Rng};
-use std::collections::HashSet;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::sync::Arc;
 
 use rayon::prelude::*;
 
+use crate::crossover;
 use crate::job;
+use crate::job_control::JobControl;
+use crate::mutation;
 use crate::nesting_runner::NestPart;
+use crate::nfp_cache::NFPCache;
+use crate::optimizer;
 use crate::packing::{PackingResult, PlacementSequence};
+use crate::selection::{self, Selection};
 
-const NO_PROGRESS_LIMIT: usize = 6;
-const POPULATION_SIZE: usize = 2;
-const MUTATION_RATE: f64 = 0.1;
+// how often we try to select a second parent that differs from the first
+const SELECTION_ATTEMPTS: usize = 10;
+// the chance that a placement of an initial individual swaps places with the one before it
+const PERTURBATION_RATE: f64 = 0.2;
 
 #[derive(Clone)]
 pub struct Population {
-    parts: Vec<NestPart>,
+    nfp_cache: Arc<NFPCache>,
+    sheets: Vec<job::Sheet>,
+    config: job::OptimizerConfig,
+    control: Arc<JobControl>,
+    // every pair of children gets its own rng seeded from this, see child_rng
+    seed: u64,
     pub individuals: Vec<PlacementSequence>,
+    // the individuals of the last generation, from fittest to least fit
+    pub ranked: Vec<PlacementSequence>,
     pub generation: usize,
     pub last_improvement: usize,
     pub last_fitness: f64,
 }
 
 impl Population {
-    pub fn new(parts: Vec<NestPart>) -> Self {
-        assert!(parts.len() >= 2);
-
-        use crate::p;
-        p!(parts.len());
-
-        let parts_copy = parts.to_owned();
-        let enumerated_parts_sorted = parts_copy.iter().enumerate().collect::<Vec<_>>();
-        // todo: sort by area instead: sorted_parts_with_index.sort_by(|x, y| x.1.area.cmp(&y.1.area));
+    pub fn new(
+        nfp_cache: Arc<NFPCache>,
+        sheets: Vec<job::Sheet>,
+        config: job::OptimizerConfig,
+        control: Arc<JobControl>,
+    ) -> Self {
+        let parts = &nfp_cache.parts;
+        assert!(!parts.is_empty());
+
+        let seed = config.seed.unwrap_or_else(optimizer::random_seed);
+        // generations never get this high, so this doesn't share its seed with a child_rng
+        let mut rng = StdRng::seed_from_u64(seed ^ (u64::MAX << 32));
+        let orderings = part_orderings(parts);
 
         let mut individuals = Vec::<PlacementSequence>::new();
 
-        // create initial population. we rotate the first part by all possible angles, then the
-        // second part, etc. until we have POPULATION_SIZE individuals. We use some math to easily
-        // iterate over the required permutations. The variable j encodes the rotation of each part.
-        // To get the rotation index of the first part we take j % part.rotations.len(), we then set
+        // create initial population. Every ordering of the parts gets an individual in turn, and
+        // each time an ordering comes up again we rotate the first part by another angle, then
+        // the second part, etc. We use some math to easily iterate over the required
+        // permutations. The variable j encodes the rotation of each part. To get the rotation
+        // index of the first part we take j % part.rotations.len(), we then set
         // j /= part.rotations.len() and repeat this process to get the rotations of the remaining
         // parts. If for example each part has 2 rotations, j's binary encoding specifies which
         // parts are rotated: a one in digit k would mean the kth part is rotated.
-        for i in 0..POPULATION_SIZE {
-            let mut j = i;
+        for i in 0..config.population_size {
+            let mut j = i / orderings.len();
             let mut placements = Vec::<job::Placement>::new();
 
-            for (part_index, part) in &enumerated_parts_sorted {
+            for &part_index in &orderings[i % orderings.len()] {
+                let part = &parts[part_index];
+                let rotations = part.rotations.initial();
                 for nth_part in 0..part.quantity {
-                    let angle = part.rotations[j % part.rotations.len()];
-                    j /= part.rotations.len();
+                    let (angle, mirrored) = rotations[j % rotations.len()];
+                    j /= rotations.len();
 
                     placements.push(job::Placement {
-                        part_index: *part_index,
+                        part_index,
                         nth_part,
                         angle,
+                        mirrored,
                     })
                 }
             }
 
+            // the first individual of every ordering keeps it as it is, the others are perturbed
+            // so the population doesn't start with only a few orderings
+            if i >= orderings.len() {
+                perturb(&mut placements, &mut rng);
+            }
+
             individuals.push(PlacementSequence { placements })
         }
 
         Population {
-            parts,
+            nfp_cache,
+            sheets,
+            config,
+            control,
+            seed,
             individuals,
+            ranked: vec![],
             generation: 0,
             last_improvement: 0,
-            last_fitness: 0.,
+            last_fitness: f64::MAX,
         }
     }
 }
@@ -76,200 +110,213 @@ impl Iterator for Population {
     fn next(&mut self) -> Option<Self::Item> {
         // Calculate next population in parallel
 
+        if self
+            .config
+            .max_generations
+            .is_some_and(|max_generations| self.generation >= max_generations)
+        {
+            return None;
+        }
+
         let packing_results = self.par_packing_results();
+        // stopped in the middle of the generation, so we report the fittest individual that was
+        // packed, see optimizer
+        if packing_results.len() < self.individuals.len() {
+            let (fittest_result, fittest_sequence) = &packing_results[0];
+            return Some(self.generation_result(fittest_result, fittest_sequence));
+        }
         self.individuals = self.next_population(&packing_results);
         let (fittest_result, fittest_sequence) = &packing_results[0];
+        let result = self.generation_result(fittest_result, fittest_sequence);
+        self.ranked = packing_results
+            .into_iter()
+            .map(|(_, individual)| individual)
+            .collect();
 
-        // Track improvements and stop if no improvement for a few generations
+        // Track improvements and stop if no improvement for a few generations. Lower fitness is
+        // better.
 
         self.generation += 1;
 
-        if self.last_fitness < fittest_result.fitness {
-            self.last_fitness = fittest_result.fitness;
+        if result.fitness < self.last_fitness {
+            self.last_fitness = result.fitness;
             self.last_improvement = 0;
         } else {
             self.last_improvement += 1;
         }
 
-        if self.last_improvement >= NO_PROGRESS_LIMIT {
+        if self.last_improvement >= self.config.stall_limit {
             return None;
         }
 
         // todo: stop if queue is too big, (maybe sent signal from manager)
 
-        Some(job::GenerationResult {
-            last_sheet_left_over: 0, // todo
-            sheet_count: 1,          // todo
-            cut_loss_ratio: 0.7,     // todo
-            placements_and_location: fittest_sequence
-                .placements
-                .iter()
-                .copied()
-                .zip(fittest_result.placed_at.iter().copied())
-                .collect::<Vec<_>>(),
-        })
+        Some(result)
     }
 }
 
 /// Some helper functions to determine next generation.
 impl Population {
+    fn generation_result(
+        &self,
+        result: &PackingResult,
+        sequence: &PlacementSequence,
+    ) -> job::GenerationResult {
+        result.generation_result(sequence, &self.nfp_cache.parts, &self.sheets, self.seed)
+    }
+
+    // packs the individuals and sorts them from fittest to least fit. Once the job should stop, the
+    // individuals that haven't been packed yet are left out, except for the first one.
     fn par_packing_results(&self) -> Vec<(PackingResult, PlacementSequence)> {
         let mut packing_results = self
             .individuals
             .par_iter()
-            .map(|individual| individual.pack())
-            .zip(self.individuals.to_owned())
+            .enumerate()
+            .filter(|&(i, _)| i == 0 || !self.control.should_stop())
+            .map(|(_, individual)| {
+                (
+                    individual.pack(&self.nfp_cache, &self.sheets),
+                    individual.to_owned(),
+                )
+            })
             .collect::<Vec<_>>();
 
-        packing_results
-            .sort_unstable_by(|(r1, _), (r2, _)| r1.fitness.partial_cmp(&r2.fitness).unwrap());
+        packing_results.sort_by(|(r1, _), (r2, _)| r1.fitness.total_cmp(&r2.fitness));
 
         packing_results
     }
 
+    // the children are bred in parallel. Each pair of children has its own rng, so the result
+    // only depends on the seed and not on the order in which the threads run.
     fn next_population(
         &self,
-        packing_results: &Vec<(PackingResult, PlacementSequence)>,
+        packing_results: &[(PackingResult, PlacementSequence)],
     ) -> Vec<PlacementSequence> {
-        let mut next_population = Vec::<PlacementSequence>::new();
-
-        loop {
-            let male_ix = random_weighted_index(packing_results.len());
-            let mut female_ix = random_weighted_index(packing_results.len() - 1);
-            if female_ix == male_ix {
-                female_ix += 1;
-            }
-
-            let male = &packing_results[male_ix].1;
-            let female = &packing_results[female_ix].1;
-
-            let (child1, child2) = &self.mate(male, female);
-
-            next_population.push(self.mutate(child1));
-            if next_population.len() == self.individuals.len() {
-                break;
-            }
+        // the elite survives, so the fittest individual never gets lost to mutation
+        let mut next_population = packing_results
+            .iter()
+            .take(self.config.elite_count.min(self.individuals.len()))
+            .map(|(_, individual)| individual.to_owned())
+            .collect::<Vec<_>>();
 
-            next_population.push(self.mutate(child2));
-            if next_population.len() == self.individuals.len() {
-                break;
-            }
-        }
+        let fitnesses = packing_results
+            .iter()
+            .map(|(result, _)| result.fitness)
+            .collect::<Vec<_>>();
+        let selection = selection::strategy(self.config.selection);
+
+        let child_count = self.individuals.len() - next_population.len();
+        let children = (0..child_count.div_ceil(2))
+            .into_par_iter()
+            .flat_map_iter(|pair| {
+                let mut rng = self.child_rng(pair);
+                let (male_ix, female_ix) = select_parents(selection.as_ref(), &fitnesses, &mut rng);
+
+                let male = &packing_results[male_ix].1;
+                let female = &packing_results[female_ix].1;
+
+                let (child1, child2) = self.mate(male, female, &mut rng);
+                [
+                    self.mutate(&child1, &mut rng),
+                    self.mutate(&child2, &mut rng),
+                ]
+            })
+            .collect::<Vec<_>>();
 
+        next_population.extend(children.into_iter().take(child_count));
         next_population
     }
 
+    // the rng for the pair of children with the given index in the current generation
+    fn child_rng(&self, pair: usize) -> StdRng {
+        let generation = (self.generation as u64) << 32;
+        StdRng::seed_from_u64(self.seed ^ (generation | pair as u64))
+    }
+
     fn mate(
         &self,
         male: &PlacementSequence,
         female: &PlacementSequence,
+        rng: &mut impl Rng,
     ) -> (PlacementSequence, PlacementSequence) {
         assert_eq!(male.placements.len(), female.placements.len());
 
-        // calc crossover point
-        let ignore_count = male.placements.len() / 10;
-        let start_index = ignore_count;
-        let end_index = male.placements.len() - ignore_count;
-        let cross_ix = rand::thread_rng().gen_range(start_index..=end_index);
-
-        let child1 = {
-            let cut_gene = &male.placements[..cross_ix];
-
-            // collect all male genes
-            let set: HashSet<(usize, u32)> = cut_gene
-                .iter()
-                .map(|p| (p.part_index, p.nth_part))
-                .collect();
-
-            // skip all male genes
-            let complement = female
-                .placements
-                .iter()
-                .filter(|p| !set.contains(&(p.part_index, p.nth_part)));
-
-            PlacementSequence {
-                placements: cut_gene
-                    .iter()
-                    .chain(complement)
-                    .map(|p| p.to_owned())
-                    .collect(),
-            }
-        };
-
-        assert_eq!(male.placements.len(), child1.placements.len());
-
-        let child2 = {
-            let cut_gene = &female.placements[..cross_ix];
-
-            // collect all male genes
-            let set: HashSet<(usize, u32)> = cut_gene
-                .iter()
-                .map(|p| (p.part_index, p.nth_part))
-                .collect();
-
-            // skip all male genes
-            let complement = female
-                .placements
-                .iter()
-                .filter(|p| !set.contains(&(p.part_index, p.nth_part)));
-
-            PlacementSequence {
-                placements: cut_gene
-                    .iter()
-                    .chain(complement)
-                    .map(|p| p.to_owned())
-                    .collect(),
-            }
-        };
-
-        assert_eq!(male.placements.len(), child2.placements.len());
-
-        (child1, child2)
+        let (child1, child2) = crossover::operator(self.config.crossover).cross(
+            &male.placements,
+            &female.placements,
+            rng,
+        );
+        (
+            PlacementSequence { placements: child1 },
+            PlacementSequence { placements: child2 },
+        )
     }
 
-    fn mutate(&self, individual: &PlacementSequence) -> PlacementSequence {
+    fn mutate(&self, individual: &PlacementSequence, rng: &mut impl Rng) -> PlacementSequence {
         let mut placements = individual.placements.to_owned();
-        let len = placements.len();
+        mutation::mutate(
+            &mut placements,
+            &self.config.mutation,
+            &self.nfp_cache.parts,
+            rng,
+        );
+        PlacementSequence { placements }
+    }
+}
 
-        // swap once in a while
-        // todo: tune with swapping random parts, not just with i+1
-        for i in 0..len - 1 {
-            if random::<f64>() > MUTATION_RATE {
-                continue;
-            }
-            placements.swap(i, i + 1)
+// the part indices sorted by descending area, bounding box width, longest edge and convex hull
+// area. Packing large parts first leaves the gaps between them to the small ones, like first fit
+// decreasing does. Orderings that are the same as an earlier one are left out.
+pub fn part_orderings(parts: &[NestPart]) -> Vec<Vec<usize>> {
+    let keys: [fn(&NestPart) -> f64; 4] = [
+        |part| part.area,
+        |part| part.polygon.bounding_rect().width(),
+        |part| {
+            part.polygon
+                .edges()
+                .map(|(a, b)| (b - a).x.hypot((b - a).y))
+                .fold(0., f64::max)
+        },
+        |part| part.polygon.convex_hull_area(),
+    ];
+
+    let mut orderings = Vec::<Vec<usize>>::new();
+    for key in keys {
+        let values = parts.iter().map(key).collect::<Vec<_>>();
+        let mut ordering = (0..parts.len()).collect::<Vec<_>>();
+        // the sort is stable, so parts with the same value stay in input order
+        ordering.sort_by(|&a, &b| values[b].total_cmp(&values[a]));
+        if !orderings.contains(&ordering) {
+            orderings.push(ordering);
         }
+    }
+    orderings
+}
 
-        // pick a different rotation once in a while
-        for placement in placements.iter_mut() {
-            if random::<f64>() > MUTATION_RATE {
-                continue;
-            }
-
-            let rotations = &self.parts[placement.part_index].rotations;
-            let mut index = rotations
-                .iter()
-                .position(|&r| r == placement.angle)
-                .unwrap();
-
-            index += rand::thread_rng().gen_range(0..rotations.len() - 1);
-            index %= rotations.len();
-            placement.angle = rotations[index];
+// swaps some placements with the next one, so the placements stay roughly in the same order
+fn perturb(placements: &mut [job::Placement], rng: &mut impl Rng) {
+    for i in 1..placements.len() {
+        if rng.gen::<f64>() < PERTURBATION_RATE {
+            placements.swap(i - 1, i);
         }
-
-        PlacementSequence { placements }
     }
 }
 
-fn random_weighted_index(len: usize) -> usize {
-    let n = len as f64;
-    let y = rand::random::<f64>() * n;
-
-    // I don't really know why this works. It could be simplified but I'm
-    // afraid it might detune the algorithm.
-    let x = -0.5 * (4.0 * n * n - 4.0 * n * (y - 2.0) + 1.0).sqrt() + n + 1.5;
-
-    x.floor() as usize
+// selects two different parents. The same individual can be selected twice, so we try again a
+// few times before taking the next one.
+fn select_parents(
+    selection: &dyn Selection,
+    fitnesses: &[f64],
+    rng: &mut impl Rng,
+) -> (usize, usize) {
+    let male = selection.select(fitnesses, rng);
+    for _ in 0..SELECTION_ATTEMPTS {
+        let female = selection.select(fitnesses, rng);
+        if female != male {
+            return (male, female);
+        }
+    }
+    (male, (male + 1) % fitnesses.len())
 }
 
 #[cfg(test)]
@@ -277,24 +324,221 @@ mod tests {
     use super::*;
     use crate::genetic_algorithm::Population;
     use crate::nest_polygon::NestPolygon;
+    use crate::nesting_runner::{NestPart, Rotations};
+    use crate::test_fixtures::{l_brackets, long_sheet, sheet, square_part};
 
     use geo::Coord;
 
+    fn control() -> Arc<JobControl> {
+        Arc::new(JobControl::new())
+    }
     #[test]
     fn mutates() {
         let parts = vec![NestPart {
             quantity: 4,
+            area: 0.,
             polygon: NestPolygon::new(vec![
                 Coord { x: 70.0, y: 10.0 },
                 Coord { x: 80.0, y: 20.0 },
                 Coord { x: 90.0, y: 40.0 },
             ]),
-            rotations: vec![0, 90, 180, 270],
+            rotations: Rotations::angles(&[0., 90., 180., 270.], false),
+            allow_mirror: false,
+            grain: None,
         }];
 
-        let population = Population::new(parts);
+        let sheets = vec![job::Sheet {
+            length: 100.,
+            width: 100.,
+            cost: 1.,
+            grain: None,
+        }];
+        let population = Population::new(
+            Arc::new(NFPCache::new(parts)),
+            sheets,
+            job::OptimizerConfig::default(),
+            control(),
+        );
         let x = &population.individuals[0];
         let _y = &population.individuals[0];
-        let _m = population.mutate(x);
+        let _m = population.mutate(x, &mut rand::thread_rng());
+    }
+
+    #[test]
+    fn seeds_with_the_largest_parts_first() {
+        let rectangle = |width: f64, length: f64| {
+            NestPolygon::new(vec![
+                Coord { x: 0., y: 0. },
+                Coord { x: width, y: 0. },
+                Coord {
+                    x: width,
+                    y: length,
+                },
+                Coord { x: 0., y: length },
+            ])
+        };
+        // by area: 2, 1, 0, by width: 0, 2, 1
+        let parts = [(30., 1.), (10., 5.), (20., 20.)]
+            .into_iter()
+            .map(|(width, length)| {
+                let polygon = rectangle(width, length);
+                NestPart {
+                    quantity: 2,
+                    area: polygon.area(),
+                    polygon,
+                    rotations: Rotations::angles(&[0.], false),
+                    allow_mirror: false,
+                    grain: None,
+                }
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(part_orderings(&parts), vec![vec![2, 1, 0], vec![0, 2, 1]]);
+
+        let sheets = vec![job::Sheet {
+            length: 100.,
+            width: 100.,
+            cost: 1.,
+            grain: None,
+        }];
+        let config = job::OptimizerConfig {
+            population_size: 8,
+            seed: Some(3),
+            ..Default::default()
+        };
+        let population = Population::new(Arc::new(NFPCache::new(parts)), sheets, config, control());
+        let part_indices = |individual: &PlacementSequence| {
+            individual
+                .placements
+                .iter()
+                .map(|p| p.part_index)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(part_indices(&population.individuals[0]), [2, 2, 1, 1, 0, 0]);
+        assert_eq!(part_indices(&population.individuals[1]), [0, 0, 2, 2, 1, 1]);
+        for individual in &population.individuals {
+            let mut genes = individual
+                .placements
+                .iter()
+                .map(|p| (p.part_index, p.nth_part))
+                .collect::<Vec<_>>();
+            genes.sort();
+            assert_eq!(genes, [(0, 0), (0, 1), (1, 0), (1, 1), (2, 0), (2, 1)]);
+        }
+        assert!(population.individuals[2..]
+            .iter()
+            .any(|individual| part_indices(individual)
+                != part_indices(&population.individuals[0])
+                && part_indices(individual) != part_indices(&population.individuals[1])));
+    }
+
+    #[test]
+    fn reports_material_usage() {
+        let parts = vec![square_part(10., 3)];
+        let sheets = vec![sheet(20., 40., 2.)];
+        let population = Population::new(
+            Arc::new(NFPCache::new(parts)),
+            sheets,
+            job::OptimizerConfig::default(),
+            control(),
+        );
+
+        let individual = &population.individuals[0];
+        let packing_result = individual.pack(&population.nfp_cache, &population.sheets);
+        let result = population.generation_result(&packing_result, individual);
+
+        assert_eq!(result.sheet_count, 1);
+        assert_eq!(result.last_sheet_used_length, 20.);
+        assert_eq!(result.last_sheet_left_over, 20);
+        assert_eq!(result.cut_loss_ratio, 0.25);
+        assert_eq!(result.sheet_utilisation, vec![0.375]);
+        assert_eq!(result.total_cost, 2.);
+        assert_eq!(result.placements_and_location.len(), 3);
+    }
+
+    #[test]
+    fn keeps_the_fittest_individual() {
+        let population = Population::new(
+            l_brackets(4, false),
+            long_sheet(),
+            job::OptimizerConfig::default(),
+            control(),
+        );
+
+        let fitnesses = population.map(|result| result.fitness).collect::<Vec<_>>();
+        assert!(fitnesses.windows(2).all(|pair| pair[1] <= pair[0]));
+    }
+
+    #[test]
+    fn stops_after_max_generations() {
+        let parts = vec![square_part(10., 3)];
+        let sheets = vec![sheet(20., 40., 2.)];
+        let config = job::OptimizerConfig {
+            population_size: 4,
+            max_generations: Some(3),
+            stall_limit: 100,
+            ..Default::default()
+        };
+        let population = Population::new(Arc::new(NFPCache::new(parts)), sheets, config, control());
+        assert_eq!(population.count(), 3);
+    }
+
+    #[test]
+    fn stops_in_the_middle_of_a_generation() {
+        let control = control();
+        let mut population = Population::new(
+            l_brackets(4, false),
+            long_sheet(),
+            job::OptimizerConfig::default(),
+            control.clone(),
+        );
+        let placements = |population: &Population| {
+            population
+                .individuals
+                .iter()
+                .flat_map(|individual| &individual.placements)
+                .map(|p| (p.part_index, p.nth_part, p.angle, p.mirrored))
+                .collect::<Vec<_>>()
+        };
+        let before = placements(&population);
+        control.cancel();
+
+        // the first individual is packed, but no children are bred
+        let result = population.next().unwrap();
+        assert_eq!(result.placements_and_location.len(), 4);
+        assert_eq!(population.generation, 0);
+        assert_eq!(placements(&population), before);
+    }
+
+    #[test]
+    fn reproduces_runs_with_the_same_seed() {
+        let run = |threads: usize| {
+            let config = job::OptimizerConfig {
+                population_size: 6,
+                mutation: job::MutationRates {
+                    swap: 0.3,
+                    rotation: 0.3,
+                    mirror: 0.3,
+                    ..Default::default()
+                },
+                max_generations: Some(4),
+                stall_limit: 100,
+                seed: Some(42),
+                ..Default::default()
+            };
+            let population = Population::new(l_brackets(5, true), long_sheet(), config, control());
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap();
+            pool.install(|| {
+                population
+                    .map(|result| serde_json::to_string(&result).unwrap())
+                    .collect::<Vec<_>>()
+            })
+        };
+
+        let results = run(1);
+        assert_eq!(results.len(), 4);
+        assert_eq!(results, run(4));
     }
 }
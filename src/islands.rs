@@ -0,0 +1,217 @@
+// the island model: several populations evolve independently, in parallel, and every
+// migration_interval generations the fittest individuals of each island migrate to the next one,
+// around in a ring. The islands explore different parts of the search space, while the migrants
+// spread good placement sequences between them.
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::sync::Arc;
+
+use rayon::prelude::*;
+
+use crate::genetic_algorithm::Population;
+use crate::job;
+use crate::job_control::JobControl;
+use crate::nfp_cache::NFPCache;
+use crate::optimizer;
+
+#[derive(Clone)]
+pub struct Islands {
+    // islands drop out once they stop improving, see Population::next
+    islands: Vec<Population>,
+    migration_interval: usize,
+    // the number of individuals that migrate, the size of the elite or at least 1, but only as many
+    // as there are children, so the elite of the receiving island stays
+    migrants: usize,
+    // the seed of the job, the islands get their own seeds derived from it
+    seed: u64,
+    generation: usize,
+}
+
+impl Islands {
+    pub fn new(
+        nfp_cache: Arc<NFPCache>,
+        sheets: Vec<job::Sheet>,
+        config: job::OptimizerConfig,
+        control: Arc<JobControl>,
+    ) -> Self {
+        let seed = config.seed.unwrap_or_else(optimizer::random_seed);
+        // the first island keeps the seed, so a single island runs like a single population
+        let mut rng = StdRng::seed_from_u64(seed);
+        let islands = (0..config.islands.max(1))
+            .map(|island| {
+                let config = job::OptimizerConfig {
+                    seed: Some(if island == 0 { seed } else { rng.gen() }),
+                    ..config.clone()
+                };
+                Population::new(nfp_cache.clone(), sheets.clone(), config, control.clone())
+            })
+            .collect();
+
+        Islands {
+            islands,
+            migration_interval: config.migration_interval.max(1),
+            migrants: config
+                .elite_count
+                .max(1)
+                .min(config.population_size - config.elite_count),
+            seed,
+            generation: 0,
+        }
+    }
+
+    // the fittest individuals of the last generation of every island replace the individuals at
+    // the end of the next island, which are children that haven't been evaluated yet
+    fn migrate(&mut self) {
+        let elites = self
+            .islands
+            .iter()
+            .map(|island| {
+                island
+                    .ranked
+                    .iter()
+                    .take(self.migrants)
+                    .cloned()
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let count = self.islands.len();
+        for (island, elite) in elites.into_iter().enumerate() {
+            let individuals = &mut self.islands[(island + 1) % count].individuals;
+            let start = individuals.len() - elite.len();
+            individuals[start..].clone_from_slice(&elite);
+        }
+    }
+}
+
+impl Iterator for Islands {
+    type Item = job::GenerationResult;
+
+    // runs a generation on every island and returns the fittest result among them
+    fn next(&mut self) -> Option<Self::Item> {
+        let results = self
+            .islands
+            .par_iter_mut()
+            .map(|island| island.next())
+            .collect::<Vec<_>>();
+
+        let mut islands = std::mem::take(&mut self.islands).into_iter();
+        let mut fittest: Option<job::GenerationResult> = None;
+        for result in results {
+            let island = islands.next().unwrap();
+            let Some(result) = result else {
+                continue;
+            };
+            self.islands.push(island);
+            if fittest
+                .as_ref()
+                .is_none_or(|fittest| result.fitness < fittest.fitness)
+            {
+                fittest = Some(result);
+            }
+        }
+
+        self.generation += 1;
+        if self.islands.len() > 1 && self.generation.is_multiple_of(self.migration_interval) {
+            self.migrate();
+        }
+
+        fittest.map(|result| job::GenerationResult {
+            seed: self.seed,
+            ..result
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packing::PlacementSequence;
+    use crate::test_fixtures::{l_brackets, long_sheet};
+
+    fn islands(config: job::OptimizerConfig) -> Islands {
+        Islands::new(
+            l_brackets(4, false),
+            long_sheet(),
+            config,
+            Arc::new(JobControl::new()),
+        )
+    }
+
+    #[test]
+    fn migrates_the_fittest_to_the_next_island() {
+        let placements = |individuals: &[PlacementSequence]| {
+            individuals
+                .iter()
+                .map(|individual| {
+                    individual
+                        .placements
+                        .iter()
+                        .map(|p| (p.part_index, p.nth_part, p.angle, p.mirrored))
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        for (elite_count, migrants) in [(0, 1), (2, 2), (3, 1)] {
+            let mut islands = islands(job::OptimizerConfig {
+                population_size: 4,
+                elite_count,
+                islands: 3,
+                migration_interval: 100,
+                stall_limit: 100,
+                seed: Some(5),
+                ..Default::default()
+            });
+            assert_eq!(islands.islands.len(), 3);
+            assert_eq!(islands.migrants, migrants);
+            islands.next();
+
+            let fittest = islands
+                .islands
+                .iter()
+                .map(|island| placements(&island.ranked[..migrants]))
+                .collect::<Vec<_>>();
+            let elites = islands
+                .islands
+                .iter()
+                .map(|island| placements(&island.individuals[..elite_count]))
+                .collect::<Vec<_>>();
+            islands.migrate();
+            for (island, fittest) in fittest.iter().enumerate() {
+                let next = (island + 1) % 3;
+                let individuals = &islands.islands[next].individuals;
+                assert_eq!(individuals.len(), 4);
+                assert_eq!(&placements(&individuals[4 - migrants..]), fittest);
+                assert_eq!(placements(&individuals[..elite_count]), elites[next]);
+            }
+        }
+    }
+
+    #[test]
+    fn reports_the_fittest_island() {
+        let config = job::OptimizerConfig {
+            population_size: 4,
+            islands: 4,
+            migration_interval: 2,
+            max_generations: Some(5),
+            stall_limit: 100,
+            seed: Some(11),
+            ..Default::default()
+        };
+        let results = islands(config.clone()).collect::<Vec<_>>();
+        assert_eq!(results.len(), 5);
+        assert!(results.iter().all(|result| result.seed == 11));
+
+        // the elite of every island survives, so the fittest of all islands only improves
+        assert!(results
+            .windows(2)
+            .all(|pair| pair[1].fitness <= pair[0].fitness));
+
+        let again = islands(config).collect::<Vec<_>>();
+        assert_eq!(
+            serde_json::to_string(&results).unwrap(),
+            serde_json::to_string(&again).unwrap()
+        );
+    }
+}
@@ -1,9 +1,8 @@
 // types for jobs and input data such as parts, sheets, etc.
 
-
 pub use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
 pub enum Status {
     Pending,
     Running,
@@ -11,13 +10,15 @@ pub enum Status {
     Failed,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub enum ErrorType {
     Timeout,
     InvalidInput,
     PartDoesNotFit,
     Cancelled,
     TooBusy,
+    // a bug in the nesting, the message has the panic message
+    Internal,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -26,46 +27,277 @@ pub struct Input {
     pub parts: Vec<Part>,
     pub sheets: Vec<Sheet>,
     pub tool_diameter: f64,
+    // milliseconds after which the job is done with the best solution found so far
     pub timeout: i32,
+    // how the corners of the parts are offset by half the tool diameter, defaults to Miter
+    pub offset_join_style: Option<JoinStyle>,
+    // parameters of the genetic algorithm, missing ones take their default
+    pub optimizer: Option<OptimizerConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct OptimizerConfig {
+    pub algorithm: Algorithm,
+    pub population_size: usize,
+    pub mutation: MutationRates,
+    pub crossover: Crossover,
+    pub selection: Selection,
+    // number of fittest individuals that are copied to the next generation unchanged
+    pub elite_count: usize,
+    // stop after this many generations, even if the solutions still improve
+    pub max_generations: Option<usize>,
+    // stop after this many generations without improvement, which are temperatures for simulated
+    // annealing
+    pub stall_limit: usize,
+    // runs with the same input and seed give the same results, random if not given. At most 2^53 - 1
+    // so it stays exact in JSON.
+    pub seed: Option<u64>,
+    // number of populations of population_size that evolve in parallel, see islands.rs
+    pub islands: usize,
+    // every this many generations the fittest individuals of each island migrate to the next one
+    pub migration_interval: usize,
+    // only used by simulated annealing, which uses the mutation rates for its moves as well
+    pub annealing: AnnealingConfig,
+}
+
+impl Default for OptimizerConfig {
+    fn default() -> Self {
+        OptimizerConfig {
+            algorithm: Algorithm::Genetic,
+            population_size: 10,
+            mutation: MutationRates::default(),
+            crossover: Crossover::OnePoint,
+            selection: Selection::LinearRank { pressure: 2. },
+            elite_count: 1,
+            max_generations: None,
+            stall_limit: 6,
+            seed: None,
+            islands: 1,
+            migration_interval: 5,
+            annealing: AnnealingConfig::default(),
+        }
+    }
+}
+
+// the search for the best placement sequence, see optimizer.rs
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Algorithm {
+    // a population of placement sequences, see genetic_algorithm.rs
+    Genetic,
+    // a single placement sequence that moves to worse neighbours less often as it cools down, see
+    // simulated_annealing.rs
+    SimulatedAnnealing,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct AnnealingConfig {
+    // temperatures are relative to the fitness: at temperature t a move that makes the fitness
+    // worse by a fraction of t is accepted with a chance of 1/e
+    pub initial_temperature: f64,
+    // stop once the temperature drops to this
+    pub final_temperature: f64,
+    pub cooling: Cooling,
+    // number of moves at each temperature, which is a generation
+    pub moves_per_temperature: usize,
+}
+
+impl Default for AnnealingConfig {
+    fn default() -> Self {
+        AnnealingConfig {
+            initial_temperature: 0.1,
+            final_temperature: 0.001,
+            cooling: Cooling::Geometric { factor: 0.9 },
+            moves_per_temperature: 20,
+        }
+    }
+}
+
+// how the temperature drops from one step to the next
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Cooling {
+    // multiplied by factor, which is below 1
+    Geometric { factor: f64 },
+    // lowered by step
+    Linear { step: f64 },
+    // the initial temperature divided by ln(e + number of steps). It cools down so slowly that it
+    // usually runs until the stall_limit, max_generations or the timeout.
+    Logarithmic,
+}
+
+// for every mutation the chance that it's applied at a placement, see mutation.rs
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct MutationRates {
+    // swaps two placements
+    pub swap: f64,
+    // reverses the order of a sequence of placements
+    pub reversal: f64,
+    // moves a placement to another position
+    pub insertion: f64,
+    // shuffles a sequence of placements
+    pub scramble: f64,
+    // picks another rotation
+    pub rotation: f64,
+    // flips a part over, if it allows mirroring
+    pub mirror: f64,
+}
+
+impl Default for MutationRates {
+    fn default() -> Self {
+        MutationRates {
+            swap: 0.1,
+            reversal: 0.02,
+            insertion: 0.05,
+            scramble: 0.01,
+            rotation: 0.1,
+            mirror: 0.1,
+        }
+    }
+}
+
+// how the parents of the next generation are selected, see selection.rs
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Selection {
+    // pressure from 1 to 2 is how many times more often the fittest individual is selected than an
+    // average one
+    LinearRank { pressure: f64 },
+    // the fittest of size randomly picked individuals
+    Tournament { size: usize },
+    // proportional to how much fitter an individual is than the least fit one
+    Roulette,
+}
+
+// how two placement sequences are combined into two children, see crossover.rs
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Crossover {
+    // the first part of one parent, followed by the remaining placements in the order of the other
+    OnePoint,
+    // order crossover (OX)
+    Order,
+    // partially mapped crossover (PMX)
+    PartiallyMapped,
+    Cycle,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum JoinStyle {
+    Miter,
+    Round,
+    Bevel,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Part {
     pub quantity: i32,
     pub contour: Vec<geo::Coord>,
-    pub rotations: Vec<i32>,
+    // cutouts in which smaller parts can be placed
+    #[serde(default)]
+    pub holes: Vec<Vec<geo::Coord>>,
+    // angles in degrees at which the part can be placed, counter clockwise around the origin of
+    // the contour. Exactly one of rotations, rotation_range and rotation_steps has to be given.
+    #[serde(default)]
+    pub rotations: Vec<f64>,
+    // any angle from the first to the second one, in whole degrees from the first one
+    pub rotation_range: Option<(f64, f64)>,
+    // this many evenly spaced angles starting at 0, e.g. 4 for 0, 90, 180 and 270
+    pub rotation_steps: Option<u32>,
+    // whether the part can be flipped over
+    #[serde(default)]
+    pub allow_mirror: bool,
+    // direction of the grain in degrees counter clockwise from the x axis of the contour. The part
+    // is only placed on sheets without grain or so that its grain runs along the grain of the sheet.
+    pub grain: Option<f64>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+// parts are placed on a sheet with x in 0..width and y in 0..length
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Sheet {
     pub length: f32,
     pub width: f32,
     pub cost: f32,
+    pub grain: Option<Grain>,
 }
 
+// direction of the grain of a sheet
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Grain {
+    // along y
+    Length,
+    // along x
+    Width,
+}
+
+// A Done update has a Timeout error when the job was stopped at its timeout instead of when the
+// solutions stopped improving. Pending updates are sent when the job is queued and whenever its
+// position in the queue changes.
 #[derive(Serialize, Deserialize)]
 pub struct Update {
     pub status: Status,
+    // number of jobs that start before this one, only set while it is Pending
+    pub queue_position: Option<usize>,
     pub nesting_solution: Option<GenerationResult>,
     pub error: Option<Error>,
+    // only set in the last update of a job that ran
+    pub nfp_cache_stats: Option<NFPCacheStats>,
 }
 
-#[derive(Serialize, Deserialize)]
+// how often the NFPs of a job were looked up in the cache and how many it holds
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct NFPCacheStats {
+    pub hits: usize,
+    pub misses: usize,
+    pub entries: usize,
+}
+
+// what a job is doing, as returned by job_status
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct JobStatus {
+    pub status: Status,
+    pub queue_position: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
 pub struct Error {
     pub error_type: ErrorType,
     pub message: String,
 }
 #[derive(Serialize, Deserialize, Clone)]
 pub struct GenerationResult {
+    // lower is better
+    pub fitness: f64,
+    // the seed of the run, which reproduces this result when it's passed in the optimizer config
+    pub seed: u64,
     pub sheet_count: i32,
+    // length of the last sheet that isn't used
     pub last_sheet_left_over: i32,
+    // length of the bounding box of the parts on the last sheet
+    pub last_sheet_used_length: f32,
+    // fraction of the consumed material that isn't part of a part, where only the used length of
+    // the last sheet is consumed
     pub cut_loss_ratio: f32,
-    pub placements_and_location: Vec<(Placement, geo::Coord)>,
+    // for every used sheet the fraction of its area that is covered by parts
+    pub sheet_utilisation: Vec<f32>,
+    // cost of all used sheets
+    pub total_cost: f32,
+    // for every used sheet the index of its size in Input::sheets
+    pub sheets: Vec<usize>,
+    // every placement with the index of the used sheet it's placed on and the location of the
+    // rotated part on that sheet
+    pub placements_and_location: Vec<(Placement, usize, geo::Coord)>,
+    // placements that didn't fit on any sheet. The job fails with PartDoesNotFit if the best
+    // solution has any.
+    pub unplaced: Vec<Placement>,
 }
 
 #[derive(Serialize, Deserialize, Copy, Clone)]
 pub struct Placement {
     pub part_index: usize,
     pub nth_part: u32,
-    pub angle: i32,
+    // the contour is mirrored in its y axis (x becomes -x) if mirrored is set, then rotated by angle
+    // degrees counter clockwise around its origin, then moved to the location of the placement
+    pub angle: f64,
+    #[serde(default)]
+    pub mirrored: bool,
 }
@@ -0,0 +1,127 @@
+// flags through which the host can cancel, pause and resume a job while it runs, and the timeout
+// of the job. The runner waits between generations while the job is paused, the optimizers check
+// whether to stop between packings.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Default)]
+pub struct JobControl {
+    cancelled: AtomicBool,
+    paused: Mutex<bool>,
+    resumed: Condvar,
+    // when the job times out, pushed back by the time the job spends paused
+    deadline: Mutex<Option<Instant>>,
+}
+
+impl JobControl {
+    pub fn new() -> JobControl {
+        JobControl::default()
+    }
+
+    // a cancelled job that is paused stops right away
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        self.resume();
+    }
+
+    pub fn pause(&self) {
+        *self.paused.lock().unwrap() = true;
+    }
+
+    pub fn resume(&self) {
+        *self.paused.lock().unwrap() = false;
+        self.resumed.notify_all();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.paused.lock().unwrap()
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    // starts the clock of a job that should stop after timeout
+    pub fn start_timeout(&self, timeout: Duration) {
+        *self.deadline.lock().unwrap() = Some(Instant::now() + timeout);
+    }
+
+    pub fn is_timed_out(&self) -> bool {
+        self.deadline
+            .lock()
+            .unwrap()
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    // whether the optimizer should stop right away, without finishing its generation
+    pub fn should_stop(&self) -> bool {
+        self.is_cancelled() || self.is_timed_out()
+    }
+
+    // blocks for as long as the job is paused. The clock of the timeout stops in the meantime.
+    pub fn wait_while_paused(&self) {
+        let paused = self.paused.lock().unwrap();
+        let start = Instant::now();
+        let _unpaused = self.resumed.wait_while(paused, |paused| *paused).unwrap();
+        if let Some(deadline) = self.deadline.lock().unwrap().as_mut() {
+            *deadline += start.elapsed();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn blocks_until_resumed_or_cancelled() {
+        for cancel in [false, true] {
+            let control = Arc::new(JobControl::new());
+            control.pause();
+
+            let waiting = {
+                let control = control.clone();
+                thread::spawn(move || {
+                    control.wait_while_paused();
+                    control.is_cancelled()
+                })
+            };
+            thread::sleep(Duration::from_millis(20));
+            assert!(!waiting.is_finished());
+
+            if cancel {
+                control.cancel();
+            } else {
+                control.resume();
+            }
+            assert_eq!(waiting.join().unwrap(), cancel);
+        }
+    }
+
+    #[test]
+    fn stops_the_clock_while_paused() {
+        let control = Arc::new(JobControl::new());
+        control.start_timeout(Duration::from_millis(50));
+        assert!(!control.should_stop());
+        control.pause();
+
+        let resuming = {
+            let control = control.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(200));
+                control.resume();
+            })
+        };
+        control.wait_while_paused();
+        resuming.join().unwrap();
+        assert!(!control.is_timed_out());
+
+        thread::sleep(Duration::from_millis(100));
+        assert!(control.is_timed_out() && control.should_stop());
+    }
+}
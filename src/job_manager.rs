@@ -0,0 +1,560 @@
+// queues jobs and runs a fixed number of them at the same time, each on its own worker thread.
+// The runners pack their individuals on the global rayon pool, so concurrent jobs share it.
+
+use std::any::Any;
+use std::collections::{HashMap, VecDeque};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+use crate::job;
+use crate::job_control::JobControl;
+use crate::nesting_runner::NestingRunner;
+use crate::validation;
+
+pub type UpdateCallback = Arc<dyn Fn(job::Update) + Send + Sync>;
+
+// the status of this many finished jobs is kept, older ones are forgotten
+const MAX_FINISHED_JOBS: usize = 1000;
+
+struct QueuedJob {
+    input: job::Input,
+    update_callback: UpdateCallback,
+    control: Arc<JobControl>,
+    // held while an update of the job is sent, so a Pending update about its queue position can't
+    // overtake the updates of the worker that took it from the queue
+    sending: Arc<Mutex<()>>,
+}
+
+// control is dropped once the job is finished, its last status is kept so it can still be queried
+struct JobEntry {
+    status: job::Status,
+    control: Option<Arc<JobControl>>,
+}
+
+#[derive(Default)]
+struct State {
+    queue: VecDeque<QueuedJob>,
+    jobs: HashMap<String, JobEntry>,
+    // finished jobs from oldest to newest
+    finished: VecDeque<String>,
+}
+
+impl State {
+    fn queue_position(&self, nesting_job_ulid: &str) -> Option<usize> {
+        self.queue
+            .iter()
+            .position(|queued| queued.input.nesting_job_ulid == nesting_job_ulid)
+    }
+}
+
+pub struct JobManager {
+    max_queued_jobs: usize,
+    max_finished_jobs: usize,
+    state: Mutex<State>,
+    job_queued: Condvar,
+}
+
+impl JobManager {
+    pub fn new(max_queued_jobs: usize) -> JobManager {
+        JobManager {
+            max_queued_jobs,
+            max_finished_jobs: MAX_FINISHED_JOBS,
+            state: Mutex::new(State::default()),
+            job_queued: Condvar::new(),
+        }
+    }
+
+    pub fn start_workers(self: &Arc<Self>, workers: usize) {
+        for _ in 0..workers {
+            let manager = self.clone();
+            thread::spawn(move || manager.run_worker());
+        }
+    }
+
+    // queues the job and sends a Pending update, or a Failed update if the job can't be queued
+    pub fn add_job(&self, input: job::Input, update_callback: UpdateCallback) {
+        let sending = Arc::new(Mutex::new(()));
+        let _sending = sending.lock().unwrap();
+        // validating large parts takes a while, so it's done before taking the lock
+        let queued = validation::validate(&input).and_then(|()| {
+            let mut state = self.state.lock().unwrap();
+            let is_active = state
+                .jobs
+                .get(&input.nesting_job_ulid)
+                .is_some_and(|entry| entry.control.is_some());
+            if is_active {
+                Err(job::Error {
+                    error_type: job::ErrorType::InvalidInput,
+                    message: format!(
+                        "job {} is already queued or running",
+                        input.nesting_job_ulid
+                    ),
+                })
+            } else if state.queue.len() >= self.max_queued_jobs {
+                Err(job::Error {
+                    error_type: job::ErrorType::TooBusy,
+                    message: format!("there are already {} jobs queued", state.queue.len()),
+                })
+            } else {
+                let control = Arc::new(JobControl::new());
+                state.jobs.insert(
+                    input.nesting_job_ulid.to_owned(),
+                    JobEntry {
+                        status: job::Status::Pending,
+                        control: Some(control.clone()),
+                    },
+                );
+                state.queue.push_back(QueuedJob {
+                    input,
+                    update_callback: update_callback.clone(),
+                    control,
+                    sending: sending.clone(),
+                });
+                Ok(state.queue.len() - 1)
+            }
+        });
+
+        // callbacks are called without holding the lock, so they can query the manager
+        match queued {
+            Ok(queue_position) => {
+                update_callback(pending_update(queue_position));
+                self.job_queued.notify_one();
+            }
+            Err(error) => update_callback(failed_update(error)),
+        }
+    }
+
+    pub fn job_status(&self, nesting_job_ulid: &str) -> Option<job::JobStatus> {
+        let state = self.state.lock().unwrap();
+        let entry = state.jobs.get(nesting_job_ulid)?;
+        Some(job::JobStatus {
+            status: entry.status,
+            queue_position: state.queue_position(nesting_job_ulid),
+        })
+    }
+
+    // the control of a job that is queued or running
+    pub fn job_control(&self, nesting_job_ulid: &str) -> Option<Arc<JobControl>> {
+        let state = self.state.lock().unwrap();
+        state.jobs.get(nesting_job_ulid)?.control.clone()
+    }
+
+    // takes a queued job off the queue and sends its Failed update right away, a running job stops
+    // at its next check. Returns false if there is no such job queued or running.
+    pub fn cancel_job(&self, nesting_job_ulid: &str) -> bool {
+        let (queued, moved_up) = {
+            let mut state = self.state.lock().unwrap();
+            let Some(queue_position) = state.queue_position(nesting_job_ulid) else {
+                let control = state
+                    .jobs
+                    .get(nesting_job_ulid)
+                    .and_then(|entry| entry.control.clone());
+                return control.map(|control| control.cancel()).is_some();
+            };
+            let queued = state.queue.remove(queue_position).unwrap();
+            let moved_up = waiting_jobs(&state.queue, queue_position);
+            (queued, moved_up)
+        };
+
+        {
+            let _sending = queued.sending.lock().unwrap();
+            self.set_status(nesting_job_ulid, job::Status::Failed);
+            (queued.update_callback)(failed_update(job::Error {
+                error_type: job::ErrorType::Cancelled,
+                message: "the job was cancelled".to_owned(),
+            }));
+        }
+        self.finish(nesting_job_ulid);
+        self.send_moved_up(moved_up);
+        true
+    }
+
+    // a paused job that is still queued is skipped by the workers until it is resumed
+    pub fn pause_job(&self, nesting_job_ulid: &str) -> bool {
+        self.job_control(nesting_job_ulid)
+            .map(|control| control.pause())
+            .is_some()
+    }
+
+    pub fn resume_job(&self, nesting_job_ulid: &str) -> bool {
+        // resumed while holding the lock, so a worker can't miss the notification between checking
+        // the queue and waiting
+        let state = self.state.lock().unwrap();
+        let Some(control) = state
+            .jobs
+            .get(nesting_job_ulid)
+            .and_then(|entry| entry.control.as_ref())
+        else {
+            return false;
+        };
+        control.resume();
+        self.job_queued.notify_all();
+        true
+    }
+
+    fn run_worker(self: Arc<Self>) {
+        loop {
+            let queued = self.next_job();
+            let nesting_job_ulid = queued.input.nesting_job_ulid.to_owned();
+
+            let update_callback = {
+                let (manager, nesting_job_ulid) = (self.clone(), nesting_job_ulid.to_owned());
+                let (update_callback, sending) =
+                    (queued.update_callback.clone(), queued.sending.clone());
+                move |update: job::Update| {
+                    let _sending = sending.lock().unwrap();
+                    manager.set_status(&nesting_job_ulid, update.status);
+                    update_callback(update);
+                }
+            };
+            // a panic fails the job, but the worker continues with the next one
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                NestingRunner::new(queued.input, Box::new(update_callback), queued.control)
+                    .map(|mut runner| runner.start())
+            }));
+            let error = match result {
+                Ok(Ok(())) => None,
+                Ok(Err(error)) => Some(error),
+                Err(panic) => Some(job::Error {
+                    error_type: job::ErrorType::Internal,
+                    message: format!("the nesting failed: {}", panic_message(panic.as_ref())),
+                }),
+            };
+            if let Some(error) = error {
+                let _sending = queued.sending.lock().unwrap();
+                self.set_status(&nesting_job_ulid, job::Status::Failed);
+                (queued.update_callback)(failed_update(error));
+            }
+
+            self.finish(&nesting_job_ulid);
+        }
+    }
+
+    // drops the control of a finished job and forgets the oldest finished jobs, so the jobs of a
+    // long running service don't pile up
+    fn finish(&self, nesting_job_ulid: &str) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(entry) = state.jobs.get_mut(nesting_job_ulid) {
+            entry.control = None;
+        }
+        state.finished.push_back(nesting_job_ulid.to_owned());
+        while state.finished.len() > self.max_finished_jobs {
+            let oldest = state.finished.pop_front().unwrap();
+            // unless it was added again in the meantime
+            if state
+                .jobs
+                .get(&oldest)
+                .is_some_and(|entry| entry.control.is_none())
+            {
+                state.jobs.remove(&oldest);
+            }
+        }
+    }
+
+    // blocks until a job that isn't paused is queued, takes it from the queue and tells the jobs
+    // behind it that they moved up
+    fn next_job(&self) -> QueuedJob {
+        let (queued, moved_up) = {
+            let state = self.state.lock().unwrap();
+            let mut state = self
+                .job_queued
+                .wait_while(state, |state| {
+                    state.queue.iter().all(|queued| queued.control.is_paused())
+                })
+                .unwrap();
+            let queue_position = state
+                .queue
+                .iter()
+                .position(|queued| !queued.control.is_paused())
+                .unwrap();
+            let queued = state.queue.remove(queue_position).unwrap();
+            if let Some(entry) = state.jobs.get_mut(&queued.input.nesting_job_ulid) {
+                entry.status = job::Status::Running;
+            }
+            let moved_up = waiting_jobs(&state.queue, queue_position);
+            (queued, moved_up)
+        };
+
+        self.send_moved_up(moved_up);
+        queued
+    }
+
+    // other workers may take these jobs from the queue before we get to them, so the position is
+    // looked up again once no other update of the job can be sent
+    fn send_moved_up(&self, moved_up: Vec<(String, UpdateCallback, Arc<Mutex<()>>)>) {
+        for (nesting_job_ulid, update_callback, sending) in moved_up {
+            let _sending = sending.lock().unwrap();
+            let queue_position = self.state.lock().unwrap().queue_position(&nesting_job_ulid);
+            if let Some(queue_position) = queue_position {
+                update_callback(pending_update(queue_position));
+            }
+        }
+    }
+
+    fn set_status(&self, nesting_job_ulid: &str, status: job::Status) {
+        if let Some(entry) = self.state.lock().unwrap().jobs.get_mut(nesting_job_ulid) {
+            entry.status = status;
+        }
+    }
+}
+
+// the jobs from queue_position on, which moved up when the job before them left the queue
+fn waiting_jobs(
+    queue: &VecDeque<QueuedJob>,
+    queue_position: usize,
+) -> Vec<(String, UpdateCallback, Arc<Mutex<()>>)> {
+    queue
+        .iter()
+        .skip(queue_position)
+        .map(|queued| {
+            (
+                queued.input.nesting_job_ulid.to_owned(),
+                queued.update_callback.clone(),
+                queued.sending.clone(),
+            )
+        })
+        .collect()
+}
+
+fn failed_update(error: job::Error) -> job::Update {
+    job::Update {
+        status: job::Status::Failed,
+        queue_position: None,
+        nfp_cache_stats: None,
+        nesting_solution: None,
+        error: Some(error),
+    }
+}
+
+pub fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
+
+fn pending_update(queue_position: usize) -> job::Update {
+    job::Update {
+        status: job::Status::Pending,
+        queue_position: Some(queue_position),
+        nfp_cache_stats: None,
+        nesting_solution: None,
+        error: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures;
+    use std::sync::mpsc::{channel, Receiver};
+    use std::time::Duration;
+
+    fn input(nesting_job_ulid: &str) -> job::Input {
+        job::Input {
+            nesting_job_ulid: nesting_job_ulid.to_owned(),
+            timeout: 0,
+            ..test_fixtures::input(vec![0., 180.])
+        }
+    }
+
+    // adds the job and returns a channel that receives the status, queue position and error type
+    // of its updates
+    fn add_job(
+        manager: &JobManager,
+        nesting_job_ulid: &str,
+    ) -> Receiver<(job::Status, Option<usize>, Option<job::ErrorType>)> {
+        let (tx, rx) = channel();
+        let tx = Mutex::new(tx);
+        manager.add_job(
+            input(nesting_job_ulid),
+            Arc::new(move |update: job::Update| {
+                let error_type = update.error.map(|error| error.error_type);
+                let _ = tx
+                    .lock()
+                    .unwrap()
+                    .send((update.status, update.queue_position, error_type));
+            }),
+        );
+        rx
+    }
+
+    fn status(status: job::Status, queue_position: Option<usize>) -> Option<job::JobStatus> {
+        Some(job::JobStatus {
+            status,
+            queue_position,
+        })
+    }
+
+    #[test]
+    fn queues_jobs_until_the_limit() {
+        let manager = JobManager::new(2);
+        let first = add_job(&manager, "first");
+        let second = add_job(&manager, "second");
+        let third = add_job(&manager, "third");
+        let again = add_job(&manager, "first");
+        let invalid = {
+            let mut input = input("invalid");
+            input.sheets = vec![];
+            let (tx, rx) = channel();
+            let tx = Mutex::new(tx);
+            manager.add_job(
+                input,
+                Arc::new(move |update: job::Update| {
+                    let _ = tx.lock().unwrap().send(update.error.unwrap().message);
+                }),
+            );
+            rx
+        };
+
+        assert_eq!(first.try_recv(), Ok((job::Status::Pending, Some(0), None)));
+        assert_eq!(second.try_recv(), Ok((job::Status::Pending, Some(1), None)));
+        assert_eq!(
+            third.try_recv(),
+            Ok((job::Status::Failed, None, Some(job::ErrorType::TooBusy)))
+        );
+        assert_eq!(
+            again.try_recv(),
+            Ok((
+                job::Status::Failed,
+                None,
+                Some(job::ErrorType::InvalidInput)
+            ))
+        );
+
+        assert_eq!(
+            manager.job_status("second"),
+            status(job::Status::Pending, Some(1))
+        );
+        assert_eq!(invalid.try_recv().as_deref(), Ok("there are no sheets"));
+        assert_eq!(manager.job_status("third"), None);
+        assert_eq!(manager.job_status("invalid"), None);
+    }
+
+    #[test]
+    fn runs_queued_jobs() {
+        let manager = Arc::new(JobManager::new(10));
+        let first = add_job(&manager, "first");
+        let second = add_job(&manager, "second");
+        manager.start_workers(2);
+
+        for updates in [first, second] {
+            let statuses = updates
+                .iter()
+                .map(|(status, _, _)| status)
+                .take_while(|&status| status != job::Status::Done)
+                .collect::<Vec<_>>();
+            assert_eq!(statuses.first(), Some(&job::Status::Pending));
+            assert_eq!(statuses.last(), Some(&job::Status::Running));
+        }
+
+        // the control is dropped right after the Done update
+        for nesting_job_ulid in ["first", "second"] {
+            while manager.job_control(nesting_job_ulid).is_some() {
+                thread::sleep(Duration::from_millis(1));
+            }
+            assert_eq!(
+                manager.job_status(nesting_job_ulid),
+                status(job::Status::Done, None)
+            );
+        }
+    }
+
+    #[test]
+    fn sends_pending_updates_before_the_job_runs() {
+        let manager = Arc::new(JobManager::new(100));
+        manager.start_workers(4);
+        let jobs = (0..40)
+            .map(|i| add_job(&manager, &format!("job {}", i)))
+            .collect::<Vec<_>>();
+
+        for updates in jobs {
+            let updates = updates
+                .iter()
+                .take_while(|(status, _, _)| *status != job::Status::Done)
+                .collect::<Vec<_>>();
+            let pending = updates
+                .iter()
+                .take_while(|(status, _, _)| *status == job::Status::Pending)
+                .map(|(_, queue_position, _)| queue_position.unwrap())
+                .collect::<Vec<_>>();
+            assert!(!pending.is_empty());
+            assert!(pending.windows(2).all(|pair| pair[1] <= pair[0]));
+            assert!(updates[pending.len()..]
+                .iter()
+                .all(|(status, _, _)| *status == job::Status::Running));
+        }
+    }
+
+    #[test]
+    fn takes_cancelled_jobs_off_the_queue() {
+        let manager = JobManager::new(2);
+        let first = add_job(&manager, "first");
+        let second = add_job(&manager, "second");
+        assert!(manager.cancel_job("first"));
+        assert!(!manager.cancel_job("first"));
+        assert!(!manager.cancel_job("unknown"));
+        let third = add_job(&manager, "third");
+
+        assert_eq!(first.try_recv(), Ok((job::Status::Pending, Some(0), None)));
+        assert_eq!(
+            first.try_recv(),
+            Ok((job::Status::Failed, None, Some(job::ErrorType::Cancelled)))
+        );
+        assert_eq!(second.try_recv(), Ok((job::Status::Pending, Some(1), None)));
+        assert_eq!(second.try_recv(), Ok((job::Status::Pending, Some(0), None)));
+        assert_eq!(third.try_recv(), Ok((job::Status::Pending, Some(1), None)));
+        assert_eq!(
+            manager.job_status("first"),
+            status(job::Status::Failed, None)
+        );
+        assert!(manager.job_control("first").is_none());
+    }
+
+    #[test]
+    fn skips_paused_jobs_until_resumed() {
+        let manager = Arc::new(JobManager::new(10));
+        let first = add_job(&manager, "first");
+        let second = add_job(&manager, "second");
+        assert!(manager.pause_job("first"));
+        manager.start_workers(1);
+
+        assert!(second
+            .iter()
+            .any(|(status, _, _)| status == job::Status::Done));
+        assert_eq!(
+            manager.job_status("first"),
+            status(job::Status::Pending, Some(0))
+        );
+        assert!(manager.resume_job("first"));
+        assert!(first
+            .iter()
+            .any(|(status, _, _)| status == job::Status::Done));
+    }
+
+    #[test]
+    fn forgets_the_oldest_finished_jobs() {
+        let mut manager = JobManager::new(10);
+        manager.max_finished_jobs = 1;
+        let manager = Arc::new(manager);
+        let _first = add_job(&manager, "first");
+        let _second = add_job(&manager, "second");
+        manager.start_workers(1);
+
+        while manager.job_status("first").is_some() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        while manager.job_control("second").is_some() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(manager.job_status("first"), None);
+        assert_eq!(
+            manager.job_status("second"),
+            status(job::Status::Done, None)
+        );
+    }
+}
@@ -1,77 +1,90 @@
-// external functions that allow to add_job and init the worker thread
-// the jobs are picked up from the job queue one by one in a separate thread
+// external functions that allow to init the worker threads, add_job, query the status of jobs and
+// cancel, pause or resume them. The jobs are picked up from the job queue by the worker threads
+//
+// Ownership of strings:
+//  - strings passed to these functions stay owned by the caller, they are only read during the call
+//  - the update string passed to the update callback is owned by this library and freed when the
+//    callback returns, so the callback has to copy it if it needs it later
+//  - strings returned by these functions, e.g. by job_status, are owned by the caller, who has to
+//    release them with free_string
 
 // NestingManager
 //  |- Part
 //  |   - NestPolygon
 //  |      - geo::Polygon
-//  |- Population
-//  |   - PlacementSequence
-//  |       - Placement (refs Part)
+//  |- Optimizer
+//  |   - Islands
+//  |   |   - Population
+//  |   |       - PlacementSequence
+//  |   |           - Placement (refs Part)
+//  |   - SimulatedAnnealing
+//  |       - PlacementSequence
 //  |- NFPCache (refs Part)
+mod crossover;
 mod genetic_algorithm;
+mod islands;
 mod job;
+mod job_control;
+mod job_manager;
+mod mutation;
 mod nest_polygon;
 mod nesting_runner;
+mod nfp_cache;
+mod optimizer;
 mod packing;
+mod selection;
+mod simulated_annealing;
+#[cfg(test)]
+mod test_fixtures;
+mod validation;
 
 use std::ffi::{CStr, CString};
 use std::os::raw::c_char;
+use std::panic::{self, AssertUnwindSafe};
 
-use std::sync::mpsc::{channel, Receiver, Sender};
-use std::thread;
+use std::sync::{Arc, OnceLock};
 
-// print some variables with p!(a, b, c)
-macro_rules! p(
-    ($($a:expr),*) => {
-        println!(concat!($(stringify!($a), " = {:#?}, "),*), $($a),*);
-    }
-);
-pub(crate) use p;
-
-use crate::nesting_runner::NestingRunner;
+use crate::job_manager::JobManager;
 
 type UpdateCallback = extern "C" fn(update: *const c_char);
 
-static mut JOB_CHANNEL: Option<Sender<(job::Input, UpdateCallback)>> = None;
-static mut JOB_RECEIVER: Option<Receiver<(job::Input, UpdateCallback)>> = None;
+// number of jobs that run at the same time and that can wait in the queue when calling init
+const DEFAULT_WORKERS: u32 = 1;
+const DEFAULT_MAX_QUEUED_JOBS: u32 = 100;
 
+static JOB_MANAGER: OnceLock<Arc<JobManager>> = OnceLock::new();
+
+#[allow(dead_code)]
 fn main() {}
 
 #[no_mangle]
 pub extern "C" fn init() {
-    // Create the channel for jobs
-    let (tx, rx) = channel();
-    unsafe {
-        JOB_CHANNEL = Some(tx);
-        JOB_RECEIVER = Some(rx);
-    }
+    init_with_config(DEFAULT_WORKERS, DEFAULT_MAX_QUEUED_JOBS);
+}
 
-    // Spawn a worker thread to process jobs
-    thread::spawn(|| {
-        loop {
-            // Wait for a job to be received
-            let (job, update_callback) = unsafe {
-                match &JOB_RECEIVER {
-                    Some(receiver) => receiver.recv().unwrap(),
-                    None => break,
-                }
-            };
-
-            // todo: update with pending if queued
-
-            // Create a NestingRunner for the job and start it
-            let runner = NestingRunner::new(
-                job,
-                Box::new(move |update| {
-                    let json_string = serde_json::to_string(&update).unwrap();
-                    let cstring = CString::new(json_string).unwrap().into_raw();
-                    update_callback(cstring);
-                }),
-            );
-            runner.start();
+/// Starts the given number of worker threads that each run one job at a time. Jobs that are added
+/// while more than max_queued_jobs jobs are waiting fail with TooBusy. Only the first call to
+/// init or init_with_config has an effect.
+#[no_mangle]
+pub extern "C" fn init_with_config(workers: u32, max_queued_jobs: u32) {
+    catch_panic((), || {
+        let manager = Arc::new(JobManager::new(max_queued_jobs as usize));
+        if JOB_MANAGER.set(manager.clone()).is_ok() {
+            manager.start_workers(workers.max(1) as usize);
         }
-    });
+    })
+}
+
+// a panic must not unwind into the host, so every external function returns the fallback instead
+fn catch_panic<T>(fallback: T, f: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(fallback)
+}
+
+// the string is only borrowed by the callback
+fn send_update(update_callback: UpdateCallback, update: job::Update) {
+    let json_string = serde_json::to_string(&update).unwrap();
+    let cstring = CString::new(json_string).unwrap();
+    update_callback(cstring.as_ptr());
 }
 
 /// # Safety
@@ -79,14 +92,139 @@ pub extern "C" fn init() {
 /// input has to point to a valid C string. Its JSON contents will be deserialized into a job::Input
 #[no_mangle]
 pub unsafe extern "C" fn add_job(input: *const c_char, update_callback: UpdateCallback) {
-    // Deserialize the input
-    let c_str = unsafe { CStr::from_ptr(input) };
-    let bytes = c_str.to_bytes();
-    let byte_slice = unsafe { std::slice::from_raw_parts(bytes.as_ptr(), bytes.len()) };
-    let job: job::Input = serde_json::from_slice(byte_slice).unwrap();
-
-    // Send the job to the worker thread
-    if let Some(channel) = unsafe { &JOB_CHANNEL } {
-        channel.send((job, update_callback)).unwrap();
+    let result = panic::catch_unwind(|| {
+        // Deserialize the input
+        let c_str = unsafe { CStr::from_ptr(input) };
+        let job: job::Input = match serde_json::from_slice(c_str.to_bytes()) {
+            Ok(job) => job,
+            Err(error) => {
+                return send_update(
+                    update_callback,
+                    failed_update(
+                        job::ErrorType::InvalidInput,
+                        format!("the input isn't a valid job: {}", error),
+                    ),
+                )
+            }
+        };
+
+        // Queue the job, the manager sends a Pending update with its position in the queue
+        let Some(manager) = JOB_MANAGER.get() else {
+            return send_update(
+                update_callback,
+                failed_update(
+                    job::ErrorType::Internal,
+                    "the library isn't initialized, call init first".to_owned(),
+                ),
+            );
+        };
+        manager.add_job(
+            job,
+            Arc::new(move |update| send_update(update_callback, update)),
+        );
+    });
+    if let Err(panic) = result {
+        let message = job_manager::panic_message(panic.as_ref());
+        catch_panic((), || {
+            send_update(
+                update_callback,
+                failed_update(
+                    job::ErrorType::Internal,
+                    format!("adding the job failed: {}", message),
+                ),
+            )
+        });
     }
 }
+
+fn failed_update(error_type: job::ErrorType, message: String) -> job::Update {
+    job::Update {
+        status: job::Status::Failed,
+        queue_position: None,
+        nfp_cache_stats: None,
+        nesting_solution: None,
+        error: Some(job::Error {
+            error_type,
+            message,
+        }),
+    }
+}
+
+/// Returns the status of the job as JSON, e.g. {"status":"Pending","queue_position":0}, or null if
+/// the job is unknown. Finished jobs keep their last status. The string has to be released with
+/// free_string.
+///
+/// # Safety
+///
+/// nesting_job_ulid has to point to a valid C string
+#[no_mangle]
+pub unsafe extern "C" fn job_status(nesting_job_ulid: *const c_char) -> *mut c_char {
+    catch_panic(std::ptr::null_mut(), || {
+        let nesting_job_ulid = unsafe { CStr::from_ptr(nesting_job_ulid) }.to_string_lossy();
+        match JOB_MANAGER
+            .get()
+            .and_then(|manager| manager.job_status(&nesting_job_ulid))
+        {
+            Some(status) => {
+                let json_string = serde_json::to_string(&status).unwrap();
+                CString::new(json_string).unwrap().into_raw()
+            }
+            None => std::ptr::null_mut(),
+        }
+    })
+}
+
+/// Releases a string returned by one of the functions of this library.
+///
+/// # Safety
+///
+/// string has to be null or a pointer returned by this library that hasn't been released yet
+#[no_mangle]
+pub unsafe extern "C" fn free_string(string: *mut c_char) {
+    if !string.is_null() {
+        catch_panic((), || drop(unsafe { CString::from_raw(string) }))
+    }
+}
+
+/// Stops the job. A running job sends a Failed update with the Cancelled error and the best
+/// solution so far, a queued job is taken off the queue and sends it without a solution right
+/// away. Returns false if there is no such job queued or running.
+///
+/// # Safety
+///
+/// nesting_job_ulid has to point to a valid C string
+#[no_mangle]
+pub unsafe extern "C" fn cancel_job(nesting_job_ulid: *const c_char) -> bool {
+    with_job_manager(nesting_job_ulid, JobManager::cancel_job)
+}
+
+/// Halts the job after the current generation until it is resumed or cancelled. A queued job
+/// that is paused keeps its place in the queue, but the jobs behind it can start before it.
+///
+/// # Safety
+///
+/// nesting_job_ulid has to point to a valid C string
+#[no_mangle]
+pub unsafe extern "C" fn pause_job(nesting_job_ulid: *const c_char) -> bool {
+    with_job_manager(nesting_job_ulid, JobManager::pause_job)
+}
+
+/// # Safety
+///
+/// nesting_job_ulid has to point to a valid C string
+#[no_mangle]
+pub unsafe extern "C" fn resume_job(nesting_job_ulid: *const c_char) -> bool {
+    with_job_manager(nesting_job_ulid, JobManager::resume_job)
+}
+
+unsafe fn with_job_manager(
+    nesting_job_ulid: *const c_char,
+    f: fn(&JobManager, &str) -> bool,
+) -> bool {
+    catch_panic(false, || {
+        let nesting_job_ulid = unsafe { CStr::from_ptr(nesting_job_ulid) }.to_string_lossy();
+        JOB_MANAGER
+            .get()
+            .is_some_and(|manager| f(manager, &nesting_job_ulid))
+    })
+}
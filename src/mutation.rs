@@ -0,0 +1,322 @@
+// mutation operators for placement sequences. Every operator has its own rate, which is the chance
+// that it's applied at a placement, so a sequence is mutated rate times its length times on
+// average. Operators only move placements around or change their angle or mirroring, so the
+// sequence stays a permutation of the same placements.
+
+use rand::seq::SliceRandom;
+use rand::{Rng, RngCore};
+
+use crate::job::{self, Placement};
+use crate::nesting_runner::NestPart;
+
+pub trait Mutation {
+    fn apply(
+        &self,
+        placements: &mut Vec<Placement>,
+        index: usize,
+        parts: &[NestPart],
+        rng: &mut dyn RngCore,
+    );
+}
+
+// swaps the placement with a random other one
+pub struct Swap;
+
+// reverses the order of the placements between this one and a random other one
+pub struct Reversal;
+
+// moves the placement to a random position
+pub struct Insertion;
+
+// shuffles the placements between this one and a random other one
+pub struct Scramble;
+
+// picks another of the rotations of the part, if it has another one
+pub struct Rotation;
+
+// flips the part over, if it can be mirrored at its angle
+pub struct Mirror;
+
+pub fn operators(rates: &job::MutationRates) -> [(f64, &'static (dyn Mutation + Sync)); 6] {
+    [
+        (rates.swap, &Swap),
+        (rates.reversal, &Reversal),
+        (rates.insertion, &Insertion),
+        (rates.scramble, &Scramble),
+        (rates.rotation, &Rotation),
+        (rates.mirror, &Mirror),
+    ]
+}
+
+pub fn mutate(
+    placements: &mut Vec<Placement>,
+    rates: &job::MutationRates,
+    parts: &[NestPart],
+    rng: &mut dyn RngCore,
+) {
+    for (rate, operator) in operators(rates) {
+        for index in 0..placements.len() {
+            if rng.gen::<f64>() < rate {
+                operator.apply(placements, index, parts, rng);
+            }
+        }
+    }
+}
+
+// applies one of the operators at a random placement, which is a move to a neighbour of the
+// sequence. Operators are picked in proportion to their rates.
+pub fn neighbour(
+    placements: &mut Vec<Placement>,
+    rates: &job::MutationRates,
+    parts: &[NestPart],
+    rng: &mut dyn RngCore,
+) {
+    let operators = operators(rates);
+    let total: f64 = operators.iter().map(|(rate, _)| rate).sum();
+    let mut random = rng.gen::<f64>() * total;
+    // rounding errors can leave random just above the total
+    let mut operator = operators[0].1;
+    for (rate, candidate) in operators {
+        if rate > 0. {
+            operator = candidate;
+            if random < rate {
+                break;
+            }
+        }
+        random -= rate;
+    }
+    let index = rng.gen_range(0..placements.len());
+    operator.apply(placements, index, parts, rng);
+}
+
+// the range from the placement to a random other one
+fn segment(len: usize, index: usize, rng: &mut dyn RngCore) -> std::ops::RangeInclusive<usize> {
+    let other = rng.gen_range(0..len);
+    index.min(other)..=index.max(other)
+}
+
+impl Mutation for Swap {
+    fn apply(
+        &self,
+        placements: &mut Vec<Placement>,
+        index: usize,
+        _: &[NestPart],
+        rng: &mut dyn RngCore,
+    ) {
+        let other = rng.gen_range(0..placements.len());
+        placements.swap(index, other);
+    }
+}
+
+impl Mutation for Reversal {
+    fn apply(
+        &self,
+        placements: &mut Vec<Placement>,
+        index: usize,
+        _: &[NestPart],
+        rng: &mut dyn RngCore,
+    ) {
+        let segment = segment(placements.len(), index, rng);
+        placements[segment].reverse();
+    }
+}
+
+impl Mutation for Insertion {
+    fn apply(
+        &self,
+        placements: &mut Vec<Placement>,
+        index: usize,
+        _: &[NestPart],
+        rng: &mut dyn RngCore,
+    ) {
+        let placement = placements.remove(index);
+        let position = rng.gen_range(0..=placements.len());
+        placements.insert(position, placement);
+    }
+}
+
+impl Mutation for Scramble {
+    fn apply(
+        &self,
+        placements: &mut Vec<Placement>,
+        index: usize,
+        _: &[NestPart],
+        rng: &mut dyn RngCore,
+    ) {
+        let segment = segment(placements.len(), index, rng);
+        placements[segment].shuffle(rng);
+    }
+}
+
+impl Mutation for Rotation {
+    fn apply(
+        &self,
+        placements: &mut Vec<Placement>,
+        index: usize,
+        parts: &[NestPart],
+        rng: &mut dyn RngCore,
+    ) {
+        // rotations that don't fit are removed, so a part can be left with only one
+        let placement = &mut placements[index];
+        if let Some((angle, mirrored)) =
+            parts[placement.part_index]
+                .rotations
+                .other(placement.angle, placement.mirrored, rng)
+        {
+            placement.angle = angle;
+            placement.mirrored = mirrored;
+        }
+    }
+}
+
+impl Mutation for Mirror {
+    fn apply(
+        &self,
+        placements: &mut Vec<Placement>,
+        index: usize,
+        parts: &[NestPart],
+        _: &mut dyn RngCore,
+    ) {
+        let placement = &mut placements[index];
+        // only if the part still runs along the grain and fits when it's flipped over
+        if parts[placement.part_index]
+            .rotations
+            .can_mirror(placement.angle, placement.mirrored)
+        {
+            placement.mirrored = !placement.mirrored;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nest_polygon::NestPolygon;
+    use crate::nesting_runner::Rotations;
+    use geo::Coord;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn part(quantity: u32, rotations: Vec<f64>, allow_mirror: bool) -> NestPart {
+        NestPart {
+            quantity,
+            area: 100.,
+            polygon: NestPolygon::new(vec![
+                Coord { x: 0., y: 0. },
+                Coord { x: 10., y: 0. },
+                Coord { x: 10., y: 10. },
+            ]),
+            rotations: Rotations::angles(&rotations, allow_mirror),
+            allow_mirror,
+            grain: None,
+        }
+    }
+
+    fn placements(parts: &[NestPart]) -> Vec<Placement> {
+        let mut placements = vec![];
+        for (part_index, part) in parts.iter().enumerate() {
+            for nth_part in 0..part.quantity {
+                placements.push(Placement {
+                    part_index,
+                    nth_part,
+                    angle: 0.,
+                    mirrored: false,
+                });
+            }
+        }
+        placements
+    }
+
+    fn genes(placements: &[Placement]) -> Vec<(usize, u32)> {
+        let mut genes = placements
+            .iter()
+            .map(|p| (p.part_index, p.nth_part))
+            .collect::<Vec<_>>();
+        genes.sort();
+        genes
+    }
+
+    // every operator on its own, applied at every placement
+    fn only(operator: usize) -> job::MutationRates {
+        let mut rates = [0.; 6];
+        rates[operator] = 1.;
+        job::MutationRates {
+            swap: rates[0],
+            reversal: rates[1],
+            insertion: rates[2],
+            scramble: rates[3],
+            rotation: rates[4],
+            mirror: rates[5],
+        }
+    }
+
+    #[test]
+    fn keeps_the_placements() {
+        let parts = vec![part(5, vec![0., 90.], true), part(4, vec![0.], false)];
+        let original = placements(&parts);
+        let mut rng = StdRng::seed_from_u64(1);
+        for operator in 0..6 {
+            let mut mutated = original.clone();
+            mutate(&mut mutated, &only(operator), &parts, &mut rng);
+            assert!(mutated.iter().zip(&original).any(|(a, b)| {
+                (a.part_index, a.nth_part, a.angle, a.mirrored)
+                    != (b.part_index, b.nth_part, b.angle, b.mirrored)
+            }));
+            for _ in 0..50 {
+                mutate(&mut mutated, &only(operator), &parts, &mut rng);
+                assert_eq!(genes(&mutated), genes(&original));
+            }
+        }
+    }
+
+    #[test]
+    fn moves_to_a_neighbour_with_one_operator() {
+        let parts = vec![part(5, vec![0., 90.], true), part(4, vec![0.], false)];
+        let original = placements(&parts);
+        let mut rng = StdRng::seed_from_u64(3);
+        for _ in 0..20 {
+            let mut mirrored = original.clone();
+            neighbour(&mut mirrored, &only(5), &parts, &mut rng);
+            let flipped = mirrored.iter().filter(|p| p.mirrored).count();
+            assert!(flipped <= 1);
+            assert!(mirrored
+                .iter()
+                .all(|p| !p.mirrored || parts[p.part_index].allow_mirror));
+
+            let mut moved = original.clone();
+            neighbour(&mut moved, &job::MutationRates::default(), &parts, &mut rng);
+            assert_eq!(genes(&moved), genes(&original));
+        }
+    }
+
+    #[test]
+    fn keeps_parts_mirrored_that_only_fit_mirrored() {
+        let mut parts = vec![part(3, vec![], true)];
+        parts[0].rotations = Rotations::Angles(vec![(30., true), (210., true)]);
+        let mut rng = StdRng::seed_from_u64(4);
+        for operator in 0..6 {
+            let mut mutated = placements(&parts);
+            for placement in &mut mutated {
+                placement.angle = 30.;
+                placement.mirrored = true;
+            }
+            for _ in 0..20 {
+                mutate(&mut mutated, &only(operator), &parts, &mut rng);
+                assert!(mutated.iter().all(|p| p.mirrored));
+            }
+        }
+    }
+
+    #[test]
+    fn mutates_single_placements_and_rotations() {
+        let parts = vec![part(1, vec![0.], false)];
+        let original = placements(&parts);
+        let mut rng = StdRng::seed_from_u64(2);
+        for operator in 0..6 {
+            let mut mutated = original.clone();
+            mutate(&mut mutated, &only(operator), &parts, &mut rng);
+            assert_eq!(mutated[0].angle, 0.);
+            assert!(!mutated[0].mirrored);
+        }
+    }
+}
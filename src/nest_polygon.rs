@@ -3,7 +3,21 @@ use std::f64::consts::{PI, TAU};
 use geo::algorithm::orient::Direction;
 use geo::algorithm::orient::Orient;
 use geo::algorithm::translate::Translate;
-use geo::{Coord, LineString, Polygon};
+use geo::{
+    unary_union, Area, BooleanOps, ConvexHull, Coord, InteriorPoint, LineString, MapCoords,
+    MultiPolygon, Polygon, Rect,
+};
+
+use crate::job::JoinStyle;
+
+// distance within which points are considered to touch an edge
+pub const EPSILON: f64 = 1e-6;
+
+// miter joins that stick out further than this times the offset distance are beveled instead
+const MITER_LIMIT: f64 = 2.0;
+// how far the segments of a round join may stick out of the arc they approximate, in mm. A fixed
+// number of segments per arc would be too coarse for large offsets and wasteful for small ones.
+const ARC_TOLERANCE: f64 = 0.01;
 
 // to draw
 use base64::{engine::general_purpose, Engine as _};
@@ -29,8 +43,25 @@ pub struct NestPolygon {
 impl NestPolygon {
     // calculates properties of input polygon and returns a NestPolygon which stores them
     pub fn new(polygon: Vec<Coord>) -> NestPolygon {
-        // todo must have more than 3 vertices
-        let mut polygon = geo::Polygon::new(LineString::from(polygon), vec![]);
+        NestPolygon::with_holes(polygon, vec![])
+    }
+
+    pub fn with_holes(mut polygon: Vec<Coord>, holes: Vec<Vec<Coord>>) -> NestPolygon {
+        // the contour has at least 3 vertices, see validation::validate
+        polygon.dedup();
+        let holes = holes
+            .into_iter()
+            .map(|mut hole| {
+                hole.dedup();
+                LineString::from(hole)
+            })
+            .collect();
+        NestPolygon::from_polygon(geo::Polygon::new(LineString::from(polygon), holes))
+    }
+
+    // like new, but keeps the interior rings of the polygon. Slopes and convexity only concern
+    // the exterior.
+    pub fn from_polygon(mut polygon: Polygon) -> NestPolygon {
         polygon = polygon.orient(Direction::Default);
         let mut slopes = vec![];
         let points = polygon.exterior();
@@ -62,10 +93,10 @@ impl NestPolygon {
             }
         }
 
-        let mut minx = std::f64::MAX;
-        let mut maxx = std::f64::MIN;
-        let mut miny = std::f64::MAX;
-        let mut maxy = std::f64::MIN;
+        let mut minx = f64::MAX;
+        let mut maxx = f64::MIN;
+        let mut miny = f64::MAX;
+        let mut maxy = f64::MIN;
         for Coord { x, y } in polygon.exterior().0.iter() {
             minx = if *x < minx { *x } else { minx };
             maxx = if *x > maxx { *x } else { maxx };
@@ -73,7 +104,7 @@ impl NestPolygon {
             maxy = if *y > maxy { *y } else { maxy };
         }
 
-        let mut bestx = std::f64::MAX;
+        let mut bestx = f64::MAX;
         for Coord { x, y } in polygon.exterior().0.iter() {
             if *y == miny {
                 bestx = if *x < bestx { *x } else { bestx };
@@ -97,11 +128,12 @@ impl NestPolygon {
     }
 
     // draws polygons in iTerm
+    #[allow(dead_code)]
     fn draw(polygons: Vec<&NestPolygon>) {
-        let mut minx = std::f64::MAX;
-        let mut maxx = std::f64::MIN;
-        let mut miny = std::f64::MAX;
-        let mut maxy = std::f64::MIN;
+        let mut minx = f64::MAX;
+        let mut maxx = f64::MIN;
+        let mut miny = f64::MAX;
+        let mut maxy = f64::MIN;
         for p in polygons.iter() {
             minx = if p.minx < minx { p.minx } else { minx };
             maxx = if p.maxx > maxx { p.maxx } else { maxx };
@@ -159,14 +191,287 @@ impl NestPolygon {
         println!("\x07");
     }
 
-    // does NFP for convex polygons
-    fn minkowski_sum(&self, other: &NestPolygon) -> NestPolygon {
-        if !self.is_convex {
-            // todo: concave version
-            panic!("can't get NFP for concave polygon")
+    pub fn area(&self) -> f64 {
+        self.polygon.unsigned_area()
+    }
+
+    pub fn convex_hull_area(&self) -> f64 {
+        self.polygon.convex_hull().unsigned_area()
+    }
+
+    pub fn bounding_rect(&self) -> Rect {
+        Rect::new(
+            Coord {
+                x: self.minx,
+                y: self.miny,
+            },
+            Coord {
+                x: self.maxx,
+                y: self.maxy,
+            },
+        )
+    }
+
+    // rotates the polygon counter clockwise by angle degrees around the origin. The origin is the
+    // reference point of a part: a placement at location means the contour is rotated like this
+    // and then translated by location.
+    pub fn rotated(&self, angle: f64) -> NestPolygon {
+        NestPolygon::from_polygon(self.polygon.map_coords(|c| rotate(c, angle)))
+    }
+
+    // mirrors the polygon in the y axis, which is the mirroring of a placement
+    pub fn mirrored(&self) -> NestPolygon {
+        NestPolygon::from_polygon(self.polygon.map_coords(|c| Coord { x: -c.x, y: c.y }))
+    }
+
+    // translating doesn't change the slopes so we can skip most of the calculations in new
+    pub fn translated(&self, offset: Coord) -> NestPolygon {
+        let mut translated = self.clone();
+        translated.polygon.translate_mut(offset.x, offset.y);
+        translated.minx += offset.x;
+        translated.maxx += offset.x;
+        translated.miny += offset.y;
+        translated.maxy += offset.y;
+        translated.bottom_left = translated.bottom_left + offset;
+        translated
+    }
+
+    // grows the polygon by distance in every direction. Holes shrink accordingly. Corners are
+    // extended according to the join style: Miter extends the edges until they meet, Round follows
+    // a circle around the corner and Bevel cuts the corner off with a straight edge.
+    //
+    // The offset polygon is the union of the polygon, a rectangle along the outside of every edge
+    // and a join at every convex corner, which takes care of edges that move past each other.
+    pub fn offset(&self, distance: f64, join_style: JoinStyle) -> NestPolygon {
+        if distance <= 0. {
+            return self.clone();
+        }
+
+        let mut pieces = vec![self.polygon.clone()];
+        for ring in std::iter::once(self.polygon.exterior()).chain(self.polygon.interiors()) {
+            // rings are oriented such that the polygon is on the left of every edge
+            let mut vertices = ring.0.clone();
+            vertices.pop();
+            let n = vertices.len();
+            let normal = |a: Coord, b: Coord| {
+                let d = b - a;
+                let length = (d.x * d.x + d.y * d.y).sqrt();
+                Coord {
+                    x: d.y / length,
+                    y: -d.x / length,
+                }
+            };
+
+            for i in 0..n {
+                let (prev, vertex, next) = (
+                    vertices[(i + n - 1) % n],
+                    vertices[i],
+                    vertices[(i + 1) % n],
+                );
+                let n1 = normal(prev, vertex);
+                let n2 = normal(vertex, next);
+
+                pieces.push(Polygon::new(
+                    LineString::from(vec![
+                        vertex,
+                        vertex + n2 * distance,
+                        next + n2 * distance,
+                        next,
+                    ]),
+                    vec![],
+                ));
+
+                if cross(prev, vertex, next) > 0. {
+                    pieces.push(Polygon::new(
+                        LineString::from(corner_join(vertex, n1, n2, distance, join_style)),
+                        vec![],
+                    ));
+                }
+            }
+        }
+
+        // growing a polygon keeps it connected
+        let offset = union(pieces)
+            .into_iter()
+            .max_by(|a, b| a.unsigned_area().total_cmp(&b.unsigned_area()))
+            .unwrap();
+        NestPolygon::from_polygon(offset)
+    }
+
+    // edges of the exterior followed by the edges of the interior rings
+    pub fn edges(&self) -> impl Iterator<Item = (Coord, Coord)> + '_ {
+        std::iter::once(self.polygon.exterior())
+            .chain(self.polygon.interiors())
+            .flat_map(|ring| ring.lines())
+            .map(|l| (l.start, l.end))
+    }
+
+    // returns the no fit polygon of other orbiting around self. Each point of the NFP is a
+    // translation of other at which it touches self without overlapping. The NFP may have holes:
+    // translations where other fits in a pocket or a hole of self, or where self fits in a hole
+    // of other.
+    pub fn no_fit_polygon(&self, other: &NestPolygon) -> NestPolygon {
+        let nfp = self.exterior_no_fit_polygon(other);
+
+        // self lies within a hole of other when other is translated by minus a translation at
+        // which self fits in the hole
+        let mut pockets = vec![];
+        for hole in self.polygon.interiors() {
+            pockets.extend(inner_fit_polygon(hole, other));
+        }
+        for hole in other.polygon.interiors() {
+            pockets.extend(
+                inner_fit_polygon(hole, self)
+                    .iter()
+                    .map(|pocket| pocket.map_coords(|c| -c)),
+            );
+        }
+        if pockets.is_empty() {
+            return nfp;
+        }
+
+        let (exterior, mut interiors) = MultiPolygon::new(vec![nfp.polygon])
+            .difference(&MultiPolygon::new(pockets))
+            .into_iter()
+            .max_by(|a, b| a.unsigned_area().total_cmp(&b.unsigned_area()))
+            .unwrap()
+            .into_inner();
+        interiors.retain(|ring| Polygon::new(ring.clone(), vec![]).unsigned_area() > EPSILON);
+        NestPolygon::from_polygon(Polygon::new(exterior, interiors))
+    }
+
+    // the NFP of the exteriors of self and other. For concave polygons we split both polygons in
+    // convex pieces and take the union of the NFPs of all pairs of pieces.
+    fn exterior_no_fit_polygon(&self, other: &NestPolygon) -> NestPolygon {
+        if self.is_convex && other.is_convex {
+            return self.minkowski_sum(other);
+        }
+
+        let others = other.convex_decomposition();
+        let partial_nfps = self
+            .convex_decomposition()
+            .iter()
+            .flat_map(|a| others.iter().map(move |b| a.minkowski_sum(b).polygon))
+            .collect::<Vec<_>>();
+
+        // the NFP of two connected polygons is connected, so apart from rounding errors the union
+        // consists of a single polygon
+        let (exterior, mut interiors) = union(partial_nfps)
+            .into_iter()
+            .max_by(|a, b| a.unsigned_area().total_cmp(&b.unsigned_area()))
+            .unwrap()
+            .into_inner();
+
+        // drop slivers left behind by rounding errors
+        interiors.retain(|ring| Polygon::new(ring.clone(), vec![]).unsigned_area() > EPSILON);
+        NestPolygon::from_polygon(Polygon::new(exterior, interiors))
+    }
+
+    // splits the exterior in convex polygons by triangulating it and merging neighbouring
+    // triangles for as long as the result stays convex (Hertel-Mehlhorn)
+    pub fn convex_decomposition(&self) -> Vec<NestPolygon> {
+        if self.is_convex {
+            return vec![NestPolygon::new(self.polygon.exterior().0.clone())];
+        }
+
+        let mut vertices = self.polygon.exterior().0.clone();
+        vertices.pop();
+        let mut pieces = triangulate(&vertices);
+
+        let mut merged = true;
+        while merged {
+            merged = false;
+            'search: for i in 0..pieces.len() {
+                for j in i + 1..pieces.len() {
+                    if let Some(piece) = merge_convex(&vertices, &pieces[i], &pieces[j]) {
+                        pieces[i] = piece;
+                        pieces.swap_remove(j);
+                        merged = true;
+                        break 'search;
+                    }
+                }
+            }
+        }
+
+        // the pieces are convex, but rounding errors in the slopes of nearly collinear edges may
+        // make from_polygon classify them as concave, which minkowski_sum can't handle. Their convex
+        // hull drops the collinear vertices.
+        pieces
+            .iter()
+            .map(|piece| NestPolygon::new(piece.iter().map(|&i| vertices[i]).collect()))
+            .map(|piece| {
+                if piece.is_convex {
+                    piece
+                } else {
+                    NestPolygon::from_polygon(piece.polygon.convex_hull())
+                }
+            })
+            .collect()
+    }
+
+    // returns the translations of self for which it fits inside a width x length rectangle
+    // with its bottom left corner at the origin, or None if it doesn't fit at all
+    pub fn inner_fit_rect(&self, width: f64, length: f64) -> Option<Rect> {
+        self.inner_fit(Rect::new((0., 0.), (width, length)))
+    }
+
+    // returns the translations of self for which its bounding box fits inside rect
+    fn inner_fit(&self, rect: Rect) -> Option<Rect> {
+        let min = Coord {
+            x: rect.min().x - self.minx,
+            y: rect.min().y - self.miny,
         };
+        let max = Coord {
+            x: rect.max().x - self.maxx,
+            y: rect.max().y - self.maxy,
+        };
+        if max.x < min.x - EPSILON || max.y < min.y - EPSILON {
+            return None;
+        }
+        Some(Rect::new(
+            min,
+            Coord {
+                x: max.x.max(min.x),
+                y: max.y.max(min.y),
+            },
+        ))
+    }
 
-        let mut current_loc = self.bottom_left;
+    // true if the point lies inside the polygon and is not within EPSILON of its boundary
+    pub fn contains_strictly(&self, point: Coord) -> bool {
+        if point.x <= self.minx + EPSILON
+            || point.x >= self.maxx - EPSILON
+            || point.y <= self.miny + EPSILON
+            || point.y >= self.maxy - EPSILON
+        {
+            return false;
+        }
+
+        let mut inside = false;
+        for (a, b) in self.edges() {
+            if distance_to_segment(point, a, b) <= EPSILON {
+                return false;
+            }
+            if (a.y > point.y) != (b.y > point.y)
+                && point.x < a.x + (point.y - a.y) / (b.y - a.y) * (b.x - a.x)
+            {
+                inside = !inside;
+            }
+        }
+        inside
+    }
+
+    // does NFP for convex polygons, see no_fit_polygon
+    fn minkowski_sum(&self, other: &NestPolygon) -> NestPolygon {
+        assert!(
+            self.is_convex && other.is_convex,
+            "minkowski_sum needs convex polygons, use no_fit_polygon instead"
+        );
+
+        // we walk the edges of self starting at its bottom vertex while the top vertex of other
+        // touches it. Subtracting other's top vertex turns these locations into translations.
+        let mut current_loc =
+            self.polygon.exterior().0[self.zero_index] - other.polygon.exterior().0[other.pi_index];
         let mut i1 = self.zero_index;
         let mut i2 = other.pi_index;
         let mut vec = Vec::<Coord>::new();
@@ -211,66 +516,627 @@ impl NestPolygon {
                 }
             }
         }
+        // the walk ends where it started, up to rounding errors. That last vertex would leave a
+        // tiny edge back to the first one, which the boolean operations don't handle well.
+        vec.pop();
         NestPolygon::new(vec)
     }
 }
 
-pub fn f() {
-    let _p1 = NestPolygon::new(vec![
-        Coord { x: 20., y: 20. },
-        Coord { x: 20., y: 40. },
-        Coord { x: 40., y: 40. },
-        Coord { x: 40., y: 20. },
-    ]);
-
-    let _p2 = NestPolygon::new(vec![
-        Coord { x: 20., y: 20. },
-        Coord { x: 30., y: 30. },
-        Coord { x: 20., y: 40. },
-        Coord { x: 10., y: 30. },
-    ]);
-
-    let mut _p3 = NestPolygon::new(vec![
-        Coord { x: 20.0, y: 10.0 },
-        Coord { x: 30.0, y: 0.0 },
-        Coord { x: 40.0, y: 0.0 },
-        Coord { x: 50.0, y: 10.0 },
-        Coord { x: 60.0, y: 20.0 },
-        Coord { x: 70.0, y: 30.0 },
-        Coord { x: 50.0, y: 70.0 },
-    ]);
-
-    let mut _p4 = NestPolygon::new(vec![
-        Coord { x: 0.0, y: 20.0 },
-        Coord { x: 10.0, y: 40.0 },
-        Coord { x: 20.0, y: 50.0 },
-        Coord { x: 40.0, y: 60.0 },
-        Coord { x: 60.0, y: 30.0 },
-        Coord { x: 40.0, y: 0.0 },
-        Coord { x: 20.0, y: 10.0 },
-    ]);
-
-    let _p5 = NestPolygon::new(vec![
-        Coord { x: 70.0, y: 10.0 },
-        Coord { x: 80.0, y: 20.0 },
-        Coord { x: 90.0, y: 40.0 },
-        Coord { x: 80.0, y: 60.0 },
-        Coord { x: 60.0, y: 70.0 },
-        Coord { x: 50.0, y: 50.0 },
-        Coord { x: 60.0, y: 30.0 },
-    ]);
-
-    let nfp = _p3.minkowski_sum(&_p4);
-    crate::p!(nfp.polygon.exterior().0.last().unwrap());
-    let trans = _p3.polygon.exterior().0[_p3.zero_index] - _p4.polygon.exterior().0[_p4.pi_index];
-    _p4.polygon.translate_mut(trans.x, trans.y);
-    // let first = nfp.polygon.exterior().0.first().unwrap();
-
-    for i in 1..nfp.polygon.exterior().0.len() {
-        let d = nfp.polygon.exterior().0[i] - nfp.polygon.exterior().0[i - 1];
-        _p4.polygon.translate_mut(d.x, d.y);
-        NestPolygon::draw(vec![&_p3, &_p4, &nfp]);
-    }
-
-    // _p2.minkowski_sum(&_p3).draw();
+// Returns the translations of polygon for which it lies within the ring. These are the translations
+// within the inner fit rectangle of the bounding box of the ring, where polygon doesn't overlap the
+// complement of the ring. To get its NFP, we take the complement within a frame around the ring and
+// split it by a vertical line through the ring, which gives polygons without holes.
+fn inner_fit_polygon(ring: &LineString, polygon: &NestPolygon) -> Vec<Polygon> {
+    let hole = NestPolygon::new(ring.0.clone());
+    let bounds = hole.bounding_rect();
+    let (Some(candidates), Some(split)) =
+        (polygon.inner_fit(bounds), hole.polygon.interior_point())
+    else {
+        return vec![];
+    };
+
+    let margin = Coord {
+        x: polygon.maxx - polygon.minx + 1.,
+        y: polygon.maxy - polygon.miny + 1.,
+    };
+    let frame = Rect::new(bounds.min() - margin, bounds.max() + margin);
+    let complement = MultiPolygon::new(vec![frame.to_polygon()])
+        .difference(&MultiPolygon::new(vec![hole.polygon.clone()]));
+    let halves = [
+        Rect::new(
+            frame.min(),
+            Coord {
+                x: split.x(),
+                y: frame.max().y,
+            },
+        ),
+        Rect::new(
+            Coord {
+                x: split.x(),
+                y: frame.min().y,
+            },
+            frame.max(),
+        ),
+    ];
+
+    let partial_nfps = halves
+        .iter()
+        .flat_map(|half| complement.intersection(&MultiPolygon::new(vec![half.to_polygon()])))
+        .map(|piece| {
+            NestPolygon::new(piece.exterior().0.clone())
+                .exterior_no_fit_polygon(polygon)
+                .polygon
+        })
+        .collect();
+
+    MultiPolygon::new(vec![candidates.to_polygon()])
+        .difference(&union(partial_nfps))
+        .into_iter()
+        .filter(|pocket| pocket.unsigned_area() > EPSILON)
+        .collect()
+}
+
+// unions the polygons in a single pass. The boolean operations of geo snap the coordinates to an
+// integer grid that depends on the extent of their input, so unioning pairwise would snap the
+// intermediate results differently and let edges that coincide drift apart.
+fn union(polygons: Vec<Polygon>) -> MultiPolygon {
+    let polygons: Vec<Polygon> = polygons
+        .into_iter()
+        .map(|p| without_tiny_edges(p).orient(Direction::Default))
+        .collect();
+    unary_union(&polygons)
+}
+
+// drops vertices within EPSILON of the previous one. Rounding errors leave such vertices behind
+// and the boolean operations may drop the area around them.
+fn without_tiny_edges(polygon: Polygon) -> Polygon {
+    let clean = |ring: &LineString| {
+        let mut vertices = ring.0.clone();
+        vertices.dedup_by(|c, previous| {
+            let d = *c - *previous;
+            d.x.hypot(d.y) <= EPSILON
+        });
+        LineString::from(vertices)
+    };
+    Polygon::new(
+        clean(polygon.exterior()),
+        polygon.interiors().iter().map(clean).collect(),
+    )
+}
+
+// the area that has to be added at a convex corner between the offset edges with normals n1 and
+// n2 to join them
+fn corner_join(
+    vertex: Coord,
+    n1: Coord,
+    n2: Coord,
+    distance: f64,
+    join_style: JoinStyle,
+) -> Vec<Coord> {
+    let cos = n1.x * n2.x + n1.y * n2.y;
+    let miter = (n1 + n2) * (distance / (1. + cos));
+    let miter_length = (miter.x * miter.x + miter.y * miter.y).sqrt();
+
+    let mut join = vec![vertex, vertex + n1 * distance];
+    match join_style {
+        JoinStyle::Miter if miter_length <= MITER_LIMIT * distance => join.push(vertex + miter),
+        JoinStyle::Round => {
+            // put the vertices slightly outside the circle so the segments don't cut into it
+            let start = n1.y.atan2(n1.x);
+            let sweep = (n1.x * n2.y - n1.y * n2.x).atan2(cos);
+            let max_step = 2. * (distance / (distance + ARC_TOLERANCE)).acos();
+            let segments = ((sweep / max_step).ceil() as usize).max(1);
+            let step = sweep / segments as f64;
+            let radius = distance / (step / 2.).cos();
+            for k in 0..segments {
+                let angle = start + step * (k as f64 + 0.5);
+                join.push(
+                    vertex
+                        + Coord {
+                            x: angle.cos(),
+                            y: angle.sin(),
+                        } * radius,
+                );
+            }
+        }
+        JoinStyle::Miter | JoinStyle::Bevel => {}
+    }
+    join.push(vertex + n2 * distance);
+    join
+}
+
+fn cross(o: Coord, a: Coord, b: Coord) -> f64 {
+    (a.x - o.x) * (b.y - o.y) - (a.y - o.y) * (b.x - o.x)
+}
+
+// ear clipping triangulation of a counter clockwise simple polygon. Returns the triangles as
+// indices into vertices.
+fn triangulate(vertices: &[Coord]) -> Vec<Vec<usize>> {
+    let mut remaining: Vec<usize> = (0..vertices.len()).collect();
+    let mut triangles = vec![];
+
+    while remaining.len() > 3 {
+        let n = remaining.len();
+        let corner = |k: usize| {
+            (
+                remaining[(k + n - 1) % n],
+                remaining[k],
+                remaining[(k + 1) % n],
+            )
+        };
+        let is_ear = |k: usize| {
+            let (a, b, c) = corner(k);
+            let (pa, pb, pc) = (vertices[a], vertices[b], vertices[c]);
+            cross(pa, pb, pc) > 0.
+                && remaining.iter().all(|&i| {
+                    i == a
+                        || i == b
+                        || i == c
+                        || cross(pa, pb, vertices[i]) < 0.
+                        || cross(pb, pc, vertices[i]) < 0.
+                        || cross(pc, pa, vertices[i]) < 0.
+                })
+        };
+
+        // rounding errors may leave us without a proper ear, in which case we cut off the most
+        // convex corner
+        let k = (0..n).find(|&k| is_ear(k)).unwrap_or_else(|| {
+            (0..n)
+                .max_by(|&k, &l| {
+                    let (a, b, c) = corner(k);
+                    let (d, e, f) = corner(l);
+                    cross(vertices[a], vertices[b], vertices[c]).total_cmp(&cross(
+                        vertices[d],
+                        vertices[e],
+                        vertices[f],
+                    ))
+                })
+                .unwrap()
+        });
+
+        let (a, b, c) = corner(k);
+        if cross(vertices[a], vertices[b], vertices[c]) != 0. {
+            triangles.push(vec![a, b, c]);
+        }
+        remaining.remove(k);
+    }
+    triangles.push(remaining);
+    triangles
+}
+
+// merges two counter clockwise convex polygons (as indices into vertices) that share an edge, if
+// the result is convex as well
+fn merge_convex(vertices: &[Coord], p: &[usize], q: &[usize]) -> Option<Vec<usize>> {
+    // find the edge u -> v of p that q has as v -> u
+    let (np, nq) = (p.len(), q.len());
+    let (i, j) = (0..np).find_map(|i| {
+        let (u, v) = (p[i], p[(i + 1) % np]);
+        (0..nq)
+            .find(|&j| q[j] == v && q[(j + 1) % nq] == u)
+            .map(|j| (i, j))
+    })?;
+
+    // walk p from v around to u, then q from u around to v, skipping the shared vertices
+    let mut merged: Vec<usize> = (1..=np).map(|k| p[(i + k) % np]).collect();
+    merged.extend((2..nq).map(|k| q[(j + k) % nq]));
+
+    let n = merged.len();
+    let convex = (0..n).all(|k| {
+        cross(
+            vertices[merged[(k + n - 1) % n]],
+            vertices[merged[k]],
+            vertices[merged[(k + 1) % n]],
+        ) >= 0.
+    });
+    convex.then_some(merged)
+}
+
+// rotates the coordinate counter clockwise by angle degrees around the origin, like
+// NestPolygon::rotated
+pub fn rotate(c: Coord, angle: f64) -> Coord {
+    let (sin, cos) = sin_cos_degrees(angle);
+    Coord {
+        x: c.x * cos - c.y * sin,
+        y: c.x * sin + c.y * cos,
+    }
+}
+
+// the angle in 0..360, where angles that are within a microdegree of each other are the same
+pub fn normalize_angle(angle: f64) -> f64 {
+    let microdegrees = (angle.rem_euclid(360.) * 1e6).round();
+    (microdegrees % 360e6) / 1e6
+}
+
+// exact values for multiples of 90 degrees so axis aligned parts stay axis aligned
+fn sin_cos_degrees(angle: f64) -> (f64, f64) {
+    match normalize_angle(angle) {
+        0. => (0., 1.),
+        90. => (1., 0.),
+        180. => (0., -1.),
+        270. => (-1., 0.),
+        a => a.to_radians().sin_cos(),
+    }
+}
+
+pub fn distance_to_segment(point: Coord, a: Coord, b: Coord) -> f64 {
+    let ab = b - a;
+    let len2 = ab.x * ab.x + ab.y * ab.y;
+    let t = if len2 == 0. {
+        0.
+    } else {
+        (((point.x - a.x) * ab.x + (point.y - a.y) * ab.y) / len2).clamp(0., 1.)
+    };
+    let d = point - (a + ab * t);
+    (d.x * d.x + d.y * d.y).sqrt()
+}
+
+// returns the intersection point of segments a1-a2 and b1-b2, ignoring parallel segments
+pub fn segment_intersection(a1: Coord, a2: Coord, b1: Coord, b2: Coord) -> Option<Coord> {
+    let r = a2 - a1;
+    let s = b2 - b1;
+    let denominator = r.x * s.y - r.y * s.x;
+    if denominator.abs() < f64::EPSILON {
+        return None;
+    }
+    let qp = b1 - a1;
+    let t = (qp.x * s.y - qp.y * s.x) / denominator;
+    let u = (qp.x * r.y - qp.y * r.x) / denominator;
+    if !(0. ..=1.).contains(&t) || !(0. ..=1.).contains(&u) {
+        return None;
+    }
+    Some(a1 + r * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geo::ConvexHull;
+
+    fn square(x: f64, y: f64, size: f64) -> NestPolygon {
+        NestPolygon::new(vec![
+            Coord { x, y },
+            Coord { x: x + size, y },
+            Coord {
+                x: x + size,
+                y: y + size,
+            },
+            Coord { x, y: y + size },
+        ])
+    }
+
+    #[test]
+    fn nfp_of_squares() {
+        let nfp = square(0., 0., 10.).no_fit_polygon(&square(0., 0., 5.));
+
+        assert_eq!(nfp.bounding_rect(), Rect::new((-5., -5.), (10., 10.)));
+        assert_eq!(nfp.polygon.unsigned_area(), 225.);
+    }
+
+    #[test]
+    fn nfp_touches_without_overlap() {
+        let p3 = NestPolygon::new(vec![
+            Coord { x: 20.0, y: 10.0 },
+            Coord { x: 30.0, y: 0.0 },
+            Coord { x: 40.0, y: 0.0 },
+            Coord { x: 50.0, y: 10.0 },
+            Coord { x: 60.0, y: 20.0 },
+            Coord { x: 70.0, y: 30.0 },
+            Coord { x: 50.0, y: 70.0 },
+        ]);
+        let p4 = NestPolygon::new(vec![
+            Coord { x: 0.0, y: 20.0 },
+            Coord { x: 10.0, y: 40.0 },
+            Coord { x: 20.0, y: 50.0 },
+            Coord { x: 40.0, y: 60.0 },
+            Coord { x: 60.0, y: 30.0 },
+            Coord { x: 40.0, y: 0.0 },
+            Coord { x: 20.0, y: 10.0 },
+        ]);
+
+        let nfp = p3.no_fit_polygon(&p4);
+
+        // for convex polygons the NFP is the convex hull of all vertex differences
+        let differences: Vec<Coord> = p3
+            .polygon
+            .exterior()
+            .0
+            .iter()
+            .flat_map(|a| p4.polygon.exterior().0.iter().map(move |b| *a - *b))
+            .collect();
+        let hull = geo::MultiPoint::from(differences).convex_hull();
+        assert!((nfp.polygon.unsigned_area() - hull.unsigned_area()).abs() < 1e-6);
+
+        // at every vertex of the NFP the polygons touch: the vertices of one polygon are never
+        // strictly inside the other
+        for t in nfp.polygon.exterior().0.iter() {
+            let moved = p4.translated(*t);
+            for c in moved.polygon.exterior().0.iter() {
+                assert!(!p3.contains_strictly(*c));
+            }
+            for c in p3.polygon.exterior().0.iter() {
+                assert!(!moved.contains_strictly(*c));
+            }
+        }
+    }
+
+    fn l_bracket() -> NestPolygon {
+        NestPolygon::new(vec![
+            Coord { x: 0., y: 0. },
+            Coord { x: 30., y: 0. },
+            Coord { x: 30., y: 10. },
+            Coord { x: 10., y: 10. },
+            Coord { x: 10., y: 30. },
+            Coord { x: 0., y: 30. },
+        ])
+    }
+
+    // a 30x30 box with a 20x20 cavity and a 4 wide opening at the top
+    fn closed_channel() -> NestPolygon {
+        NestPolygon::new(vec![
+            Coord { x: 0., y: 0. },
+            Coord { x: 30., y: 0. },
+            Coord { x: 30., y: 30. },
+            Coord { x: 17., y: 30. },
+            Coord { x: 17., y: 25. },
+            Coord { x: 25., y: 25. },
+            Coord { x: 25., y: 5. },
+            Coord { x: 5., y: 5. },
+            Coord { x: 5., y: 25. },
+            Coord { x: 13., y: 25. },
+            Coord { x: 13., y: 30. },
+            Coord { x: 0., y: 30. },
+        ])
+    }
+
+    // checks the NFP against the actual overlap of the polygons on a grid of translations. The
+    // polygons touch on the boundary of the NFP, so translations close to it are skipped.
+    fn assert_nfp_matches_overlap(a: &NestPolygon, b: &NestPolygon) {
+        let nfp = a.no_fit_polygon(b);
+        for i in -25..25 {
+            for j in -25..25 {
+                let t = Coord {
+                    x: i as f64 * 2.3 + 0.1,
+                    y: j as f64 * 2.3 + 0.1,
+                };
+                if nfp
+                    .edges()
+                    .any(|(p, q)| distance_to_segment(t, p, q) < 1e-3)
+                {
+                    continue;
+                }
+                let overlap = a
+                    .polygon
+                    .intersection(&b.translated(t).polygon)
+                    .unsigned_area();
+                assert_eq!(nfp.contains_strictly(t), overlap > 1e-9, "at {:?}", t);
+            }
+        }
+    }
+
+    #[test]
+    fn convex_decomposition() {
+        let pieces = l_bracket().convex_decomposition();
+        assert_eq!(pieces.len(), 2);
+        assert!(pieces.iter().all(|p| p.is_convex));
+        let area: f64 = pieces.iter().map(|p| p.polygon.unsigned_area()).sum();
+        assert_eq!(area, 500.);
+
+        let pieces = closed_channel().convex_decomposition();
+        let area: f64 = pieces.iter().map(|p| p.polygon.unsigned_area()).sum();
+        assert!(pieces.iter().all(|p| p.is_convex));
+        assert_eq!(area, closed_channel().polygon.unsigned_area());
+    }
+
+    #[test]
+    fn nfp_of_concave_polygons() {
+        assert_nfp_matches_overlap(&l_bracket(), &square(0., 0., 10.));
+        assert_nfp_matches_overlap(&square(0., 0., 10.), &l_bracket());
+        assert_nfp_matches_overlap(&l_bracket(), &l_bracket().rotated(180.));
+        assert_nfp_matches_overlap(&closed_channel(), &l_bracket());
+    }
+
+    #[test]
+    fn nfp_at_any_angle() {
+        // these angles used to make the boolean operations of geo panic or hang
+        for angle in [35.21, 75.45, 140.84] {
+            assert_nfp_matches_overlap(&l_bracket(), &l_bracket().rotated(angle));
+        }
+        let offset = l_bracket().offset(0.5, JoinStyle::Miter);
+        for k in 0..72 {
+            let angle = k as f64 * 5.03;
+            assert_nfp_matches_overlap(
+                &offset,
+                &l_bracket().rotated(angle).offset(0.5, JoinStyle::Miter),
+            );
+        }
+        let small_bracket = NestPolygon::new(
+            l_bracket()
+                .polygon
+                .exterior()
+                .0
+                .iter()
+                .map(|&c| c * (2. / 3.))
+                .collect(),
+        );
+        assert_nfp_matches_overlap(&small_bracket, &square(0., 0., 23.).rotated(123.41));
+    }
+
+    #[test]
+    fn nfp_of_almost_collinear_vertices() {
+        // the three vertices at the bottom right lie on a line, up to rounding errors
+        let heptagon = NestPolygon::new(vec![
+            Coord {
+                x: -6.2137612428890945,
+                y: 4.631594889060287,
+            },
+            Coord {
+                x: -11.891200139156544,
+                y: -2.9852737312553765,
+            },
+            Coord {
+                x: -4.4200312193774645,
+                y: -10.11315104305916,
+            },
+            Coord {
+                x: 3.0177784245359622,
+                y: -5.991077814584388,
+            },
+            Coord {
+                x: 10.455588068449389,
+                y: -1.8690045861096172,
+            },
+            Coord {
+                x: 7.780246669303656,
+                y: 6.673099861742619,
+            },
+            Coord {
+                x: -0.2774276388290593,
+                y: 9.249218015876462,
+            },
+        ]);
+        assert!(heptagon.convex_decomposition().iter().all(|p| p.is_convex));
+        assert_nfp_matches_overlap(&heptagon, &l_bracket().rotated(30.));
+    }
+
+    #[test]
+    fn nfp_has_pockets() {
+        let nfp = closed_channel().no_fit_polygon(&square(0., 0., 10.));
+        assert_eq!(nfp.polygon.interiors().len(), 1);
+        assert!(!nfp.contains_strictly(Coord { x: 10., y: 10. }));
+        assert_nfp_matches_overlap(&closed_channel(), &square(0., 0., 10.));
+    }
+
+    fn frame() -> NestPolygon {
+        NestPolygon::with_holes(
+            vec![
+                Coord { x: 0., y: 0. },
+                Coord { x: 30., y: 0. },
+                Coord { x: 30., y: 30. },
+                Coord { x: 0., y: 30. },
+            ],
+            vec![vec![
+                Coord { x: 10., y: 10. },
+                Coord { x: 20., y: 10. },
+                Coord { x: 20., y: 20. },
+                Coord { x: 10., y: 20. },
+            ]],
+        )
+    }
+
+    #[test]
+    fn nfp_has_pockets_for_holes() {
+        let nfp = frame().no_fit_polygon(&square(0., 0., 5.));
+        assert_eq!(nfp.polygon.interiors().len(), 1);
+        assert!(!nfp.contains_strictly(Coord { x: 12., y: 12. }));
+        assert_nfp_matches_overlap(&frame(), &square(0., 0., 5.));
+        assert_nfp_matches_overlap(&frame(), &l_bracket().rotated(90.));
+
+        // the frame can be placed around a square as well
+        let nfp = square(0., 0., 5.).no_fit_polygon(&frame());
+        assert!(!nfp.contains_strictly(Coord { x: -12., y: -12. }));
+        assert_nfp_matches_overlap(&square(0., 0., 5.), &frame());
+        assert_nfp_matches_overlap(&frame(), &frame());
+
+        let l_shaped_hole = NestPolygon::with_holes(
+            vec![
+                Coord { x: -5., y: -5. },
+                Coord { x: 40., y: -5. },
+                Coord { x: 40., y: 40. },
+                Coord { x: -5., y: 40. },
+            ],
+            vec![l_bracket().polygon.exterior().0.clone()],
+        );
+        assert_nfp_matches_overlap(&l_shaped_hole, &square(0., 0., 7.));
+        assert_nfp_matches_overlap(&l_shaped_hole, &square(0., 0., 12.));
+    }
+
+    #[test]
+    fn offsets_convex_corners() {
+        let s = square(0., 0., 10.);
+
+        let miter = s.offset(1., JoinStyle::Miter);
+        assert_eq!(miter.bounding_rect(), Rect::new((-1., -1.), (11., 11.)));
+        assert!((miter.polygon.unsigned_area() - 144.).abs() < 1e-9);
+
+        let bevel = s.offset(1., JoinStyle::Bevel);
+        assert!((bevel.polygon.unsigned_area() - 142.).abs() < 1e-9);
+
+        // slightly more than a rounded square, as the arcs are approximated from the outside
+        let round = s.offset(1., JoinStyle::Round).polygon.unsigned_area();
+        assert!(round > 140. + PI && round < 140. + PI + TAU * ARC_TOLERANCE);
+    }
+
+    #[test]
+    fn approximates_arcs_within_the_tolerance() {
+        let n1 = Coord { x: 1., y: 0. };
+        let n2 = Coord { x: 0., y: 1. };
+        let mut segments = vec![];
+        for distance in [0.1, 1., 100.] {
+            let join = corner_join(Coord::zero(), n1, n2, distance, JoinStyle::Round);
+            let arc = &join[1..];
+            for pair in arc.windows(2) {
+                let middle = (pair[0] + pair[1]) * 0.5;
+                assert!(middle.x.hypot(middle.y) >= distance - 1e-9);
+                assert!(pair[0].x.hypot(pair[0].y) <= distance + ARC_TOLERANCE + 1e-9);
+            }
+            segments.push(arc.len() - 1);
+        }
+        // the number of vertices only grows with the square root of the offset
+        assert_eq!(segments, vec![3, 7, 57]);
+    }
+
+    #[test]
+    fn offsets_concave_corners() {
+        let offset = l_bracket().offset(1., JoinStyle::Miter);
+        assert_eq!(offset.bounding_rect(), Rect::new((-1., -1.), (31., 31.)));
+        assert!((offset.polygon.unsigned_area() - 624.).abs() < 1e-9);
+        assert!(!offset.contains_strictly(Coord { x: 11.5, y: 11.5 }));
+        assert!(offset.contains_strictly(Coord { x: 10.5, y: 10.5 }));
+    }
+
+    #[test]
+    fn offsets_shrink_holes() {
+        let frame = frame();
+        let offset = frame.offset(1., JoinStyle::Miter);
+        let hole = Polygon::new(offset.polygon.interiors()[0].clone(), vec![]);
+        assert!((hole.unsigned_area() - 64.).abs() < 1e-9);
+
+        assert!(frame
+            .offset(5., JoinStyle::Miter)
+            .polygon
+            .interiors()
+            .is_empty());
+    }
+
+    #[test]
+    fn rotates_around_origin() {
+        let rotated = square(10., 0., 10.).rotated(90.);
+        assert_eq!(rotated.bounding_rect(), Rect::new((-10., 10.), (0., 20.)));
+        let rotated = square(10., 0., 10.).rotated(-270.);
+        assert_eq!(rotated.bounding_rect(), Rect::new((-10., 10.), (0., 20.)));
+
+        let c = rotate(Coord { x: 10., y: 0. }, 45.);
+        assert!((c.x - 50f64.sqrt()).abs() < EPSILON && (c.y - 50f64.sqrt()).abs() < EPSILON);
+        assert_eq!(normalize_angle(-90.), 270.);
+
+        let mirrored = square(10., 0., 10.).mirrored();
+        assert_eq!(mirrored.bounding_rect(), Rect::new((-20., 0.), (-10., 10.)));
+        assert_eq!(normalize_angle(359.999_999_9), 0.);
+    }
+
+    #[test]
+    fn inner_fit_rect() {
+        let ifp = square(5., 5., 10.).inner_fit_rect(30., 20.).unwrap();
+        assert_eq!(ifp, Rect::new((-5., -5.), (15., 5.)));
+        assert!(square(0., 0., 10.).inner_fit_rect(5., 20.).is_none());
+    }
+
+    #[test]
+    fn strict_containment() {
+        let s = square(0., 0., 10.);
+        assert!(s.contains_strictly(Coord { x: 5., y: 5. }));
+        assert!(!s.contains_strictly(Coord { x: 10., y: 5. }));
+        assert!(!s.contains_strictly(Coord { x: 11., y: 5. }));
+    }
 }
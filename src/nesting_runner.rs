@@ -1,65 +1,738 @@
-use crate::genetic_algorithm::Population;
+use rand::Rng;
+use std::sync::Arc;
+use std::time::Duration;
+
 use crate::job;
-use crate::nest_polygon::NestPolygon;
+use crate::job_control::JobControl;
+use crate::nest_polygon::{normalize_angle, NestPolygon, EPSILON};
+use crate::nfp_cache::NFPCache;
+use crate::optimizer::{self, Optimizer};
 
+// polygon is the contour of the part grown by half the tool diameter. Growing doesn't move the
+// contour, so the locations at which the polygons are placed apply to the original contours too.
+// area is the area of the original contour.
 #[derive(Clone, Debug)]
 pub struct NestPart {
     pub quantity: u32,
+    pub area: f64,
     pub polygon: NestPolygon,
-    pub rotations: Vec<i32>,
+    pub rotations: Rotations,
+    pub allow_mirror: bool,
+    // see job::Part::grain
+    pub grain: Option<f64>,
+}
+
+// the angles in degrees at which a part can be placed, each with whether the part is mirrored
+// at it. A part that can be mirrored has both variants of an angle, unless only one of them runs
+// along the grain or fits on the sheets.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Rotations {
+    Angles(Vec<(f64, bool)>),
+    // any angle from from to to, in steps of RANGE_RESOLUTION, with the mirroring of fitting.
+    // fitting are the angles of RANGE_SAMPLES evenly spread over the range at which the part fits
+    // on one of the sheets, placements start at these.
+    Range {
+        from: f64,
+        to: f64,
+        fitting: Vec<(f64, bool)>,
+    },
+}
+
+// angles in a range are rounded to this many degrees so the NFPs of parts can be cached. Every
+// relative angle of two parts has its own NFP, so a finer resolution means fewer cache hits.
+const RANGE_RESOLUTION: f64 = 1.;
+// number of angles in a range at which we check whether a part fits
+const RANGE_SAMPLES: usize = 360;
+
+impl Rotations {
+    pub fn from_part(part: &job::Part) -> Rotations {
+        if let Some((from, to)) = part.rotation_range {
+            Rotations::range(from, to, part.allow_mirror)
+        } else if let Some(steps) = part.rotation_steps {
+            let angles = (0..steps)
+                .map(|i| 360. * i as f64 / steps as f64)
+                .collect::<Vec<_>>();
+            Rotations::angles(&angles, part.allow_mirror)
+        } else {
+            Rotations::angles(&part.rotations, part.allow_mirror)
+        }
+    }
+
+    pub fn angles(angles: &[f64], allow_mirror: bool) -> Rotations {
+        Rotations::Angles(variants(angles.iter().copied(), allow_mirror))
+    }
+
+    // a range with all of its samples fitting, until remove_rotations_that_do_not_fit
+    pub fn range(from: f64, to: f64, allow_mirror: bool) -> Rotations {
+        let steps = ((to - from) / RANGE_RESOLUTION).round();
+        let mut samples = (0..=RANGE_SAMPLES)
+            .map(|i| from + RANGE_RESOLUTION * (steps * i as f64 / RANGE_SAMPLES as f64).round())
+            .collect::<Vec<_>>();
+        samples.dedup();
+        Rotations::Range {
+            from,
+            to,
+            fitting: variants(samples.into_iter(), allow_mirror),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        matches!(self, Rotations::Angles(angles) if angles.is_empty())
+    }
+
+    // the angles and mirroring the initial population is built from
+    pub fn initial(&self) -> Vec<(f64, bool)> {
+        match self {
+            Rotations::Angles(angles) => angles.to_owned(),
+            Rotations::Range { fitting, .. } => fitting.to_owned(),
+        }
+    }
+
+    // picks another angle, with the same mirroring if possible, or returns None if there is no
+    // other angle
+    pub fn other(
+        &self,
+        angle: f64,
+        mirrored: bool,
+        rng: &mut (impl Rng + ?Sized),
+    ) -> Option<(f64, bool)> {
+        match self {
+            Rotations::Angles(angles) => {
+                let others = angles
+                    .iter()
+                    .filter(|&&(a, _)| (a - angle).abs() >= EPSILON)
+                    .collect::<Vec<_>>();
+                let same_mirroring = others
+                    .iter()
+                    .filter(|&&&(_, m)| m == mirrored)
+                    .copied()
+                    .collect::<Vec<_>>();
+                let others = if same_mirroring.is_empty() {
+                    others
+                } else {
+                    same_mirroring
+                };
+                (!others.is_empty()).then(|| *others[rng.gen_range(0..others.len())])
+            }
+            Rotations::Range { from, to, .. } => {
+                let steps = ((to - from) / RANGE_RESOLUTION).round() as u32;
+                if steps == 0 {
+                    return None;
+                }
+                Some((
+                    from + RANGE_RESOLUTION * rng.gen_range(0..=steps) as f64,
+                    mirrored,
+                ))
+            }
+        }
+    }
+
+    // whether the part can be flipped over at this angle
+    pub fn can_mirror(&self, angle: f64, mirrored: bool) -> bool {
+        match self {
+            Rotations::Angles(angles) => angles
+                .iter()
+                .any(|&(a, m)| m != mirrored && (a - angle).abs() < EPSILON),
+            Rotations::Range { fitting, .. } => fitting.iter().any(|&(_, m)| m != mirrored),
+        }
+    }
+}
+
+fn variants(angles: impl Iterator<Item = f64>, allow_mirror: bool) -> Vec<(f64, bool)> {
+    angles
+        .flat_map(|angle| {
+            let mirrored = allow_mirror.then_some((angle, true));
+            std::iter::once((angle, false)).chain(mirrored)
+        })
+        .collect()
+}
+
+impl NestPart {
+    // whether the grain of the part runs along the grain of the sheet when it's placed like this.
+    // Grain has no orientation, so turning the part around keeps it along the grain.
+    pub fn fits_grain(&self, angle: f64, mirrored: bool, sheet: &job::Sheet) -> bool {
+        let (Some(part_grain), Some(sheet_grain)) = (self.grain, sheet.grain) else {
+            return true;
+        };
+        let part_grain = if mirrored {
+            180. - part_grain
+        } else {
+            part_grain
+        };
+        let difference = normalize_angle(part_grain + angle - grain_angle(sheet_grain));
+        difference == 0. || difference == 180.
+    }
+
+    // replaces a range of rotations by the angles in it at which the part runs along the grain of
+    // a sheet, unless the part can be placed at any angle on one of the sheets
+    fn restrict_rotations_to_grain(&mut self, sheets: &[job::Sheet]) {
+        let (Some(part_grain), &Rotations::Range { from, to, .. }) = (self.grain, &self.rotations)
+        else {
+            return;
+        };
+        if sheets.iter().any(|sheet| sheet.grain.is_none()) {
+            return;
+        }
+
+        let mut part_grains = vec![part_grain];
+        if self.allow_mirror {
+            part_grains.push(180. - part_grain);
+        }
+        let mut angles = vec![];
+        for sheet_grain in sheets.iter().filter_map(|sheet| sheet.grain) {
+            for (part_grain, mirrored) in part_grains.iter().zip([false, true]) {
+                // the first angle at or after from that aligns the grains, then every half turn
+                let mut angle =
+                    from + (grain_angle(sheet_grain) - part_grain - from).rem_euclid(180.);
+                while angle <= to + EPSILON {
+                    angles.push((angle, mirrored));
+                    angle += 180.;
+                }
+            }
+        }
+        angles.sort_by(|(a1, m1), (a2, m2)| a1.total_cmp(a2).then(m1.cmp(m2)));
+        angles.dedup_by(|(a1, m1), (a2, m2)| (*a1 - *a2).abs() < EPSILON && m1 == m2);
+        self.rotations = Rotations::Angles(angles);
+    }
+
+    // keeps the rotations for which the part fits on at least one of the sheets. A range is kept
+    // as a whole if the part fits at any of its samples, and only those samples are kept to start
+    // from. Mirrored and unmirrored variants of an angle are kept or removed on their own.
+    fn remove_rotations_that_do_not_fit(&mut self, sheets: &[job::Sheet]) {
+        let mirrored_polygon = self.polygon.mirrored();
+        let fits = |&(angle, mirrored): &(f64, bool)| {
+            let polygon = if mirrored {
+                &mirrored_polygon
+            } else {
+                &self.polygon
+            };
+            let rotated = polygon.rotated(angle);
+            sheets.iter().any(|sheet| {
+                self.fits_grain(angle, mirrored, sheet)
+                    && rotated
+                        .inner_fit_rect(sheet.width as f64, sheet.length as f64)
+                        .is_some()
+            })
+        };
+        self.rotations = match &self.rotations {
+            Rotations::Angles(angles) => {
+                Rotations::Angles(angles.iter().copied().filter(fits).collect())
+            }
+            Rotations::Range { from, to, fitting } => {
+                let fitting = fitting.iter().copied().filter(fits).collect::<Vec<_>>();
+                if fitting.is_empty() {
+                    Rotations::Angles(vec![])
+                } else {
+                    Rotations::Range {
+                        from: *from,
+                        to: *to,
+                        fitting,
+                    }
+                }
+            }
+        };
+    }
+}
+
+fn grain_angle(grain: job::Grain) -> f64 {
+    match grain {
+        job::Grain::Width => 0.,
+        job::Grain::Length => 90.,
+    }
 }
 
 pub struct NestingRunner {
     job: job::Input,
     update_callback: Box<dyn Fn(job::Update)>,
-    // nfp_cache: NFPCache,
-    population: Box<Population>,
+    control: Arc<JobControl>,
+    nfp_cache: Arc<NFPCache>,
+    optimizer: Box<dyn Optimizer>,
     best_solution: Option<job::GenerationResult>,
 }
 
 impl NestingRunner {
-    pub fn new(job: job::Input, update_callback: Box<dyn Fn(job::Update)>) -> NestingRunner {
-        let parts = job
+    // prepares the parts and population for the job, or returns the error that should be sent to
+    // the client if the job can't be run. The job has been validated when it was added, see
+    // JobManager::add_job.
+    pub fn new(
+        job: job::Input,
+        update_callback: Box<dyn Fn(job::Update)>,
+        control: Arc<JobControl>,
+    ) -> Result<NestingRunner, job::Error> {
+        let join_style = job.offset_join_style.unwrap_or(job::JoinStyle::Miter);
+        let mut parts: Vec<NestPart> = job
             .parts
             .iter()
-            .map(|part| NestPart {
-                quantity: part.quantity as u32,
-                polygon: NestPolygon::new(part.contour.to_owned()),
-                rotations: part.rotations.to_owned(),
+            .map(|part| {
+                let polygon =
+                    NestPolygon::with_holes(part.contour.to_owned(), part.holes.to_owned());
+                NestPart {
+                    quantity: part.quantity as u32,
+                    area: polygon.area(),
+                    polygon: polygon.offset(job.tool_diameter / 2., join_style),
+                    rotations: Rotations::from_part(part),
+                    allow_mirror: part.allow_mirror,
+                    grain: part.grain,
+                }
             })
             .collect();
 
-        let population = Box::new(Population::new(parts));
+        for (part_index, part) in parts.iter_mut().enumerate() {
+            part.restrict_rotations_to_grain(&job.sheets);
+            part.remove_rotations_that_do_not_fit(&job.sheets);
+            if part.rotations.is_empty() {
+                return Err(job::Error {
+                    error_type: job::ErrorType::PartDoesNotFit,
+                    message: format!("part {} does not fit on any of the sheets", part_index),
+                });
+            }
+        }
 
-        // todo:
-        // - setup bin
-        // - apply tool_diameter / 2 offsets to parts and other calculations
-        // - remove rotations for which parts don't fit
-        // - respond with Err if a part doesn't fit
-        // - setup NFPCache
+        let nfp_cache = Arc::new(NFPCache::new(parts));
+        let optimizer = optimizer::optimizer(
+            nfp_cache.clone(),
+            job.sheets.clone(),
+            job.optimizer.clone().unwrap_or_default(),
+            control.clone(),
+        );
 
-        NestingRunner {
+        Ok(NestingRunner {
             job,
-            population,
+            nfp_cache,
+            optimizer,
             best_solution: None,
             update_callback,
-        }
+            control,
+        })
     }
 
-    pub fn start(&self) {
-        // iterate over generations
-        for results in self.population.clone() {
+    pub fn start(&mut self) {
+        // timeout is in milliseconds. The optimizer stops in the middle of a generation once it
+        // passes, not counting the time the job is paused.
+        self.control
+            .start_timeout(Duration::from_millis(self.job.timeout.max(0) as u64));
+
+        // iterate over generations, checking in between whether the job is paused or cancelled
+        loop {
+            self.control.wait_while_paused();
+            if self.control.is_cancelled() {
+                break;
+            }
+
+            let Some(results) = self.optimizer.next() else {
+                break;
+            };
+            // the fittest solution of a generation can be worse than an earlier one
+            if self
+                .best_solution
+                .as_ref()
+                .is_none_or(|best| results.fitness < best.fitness)
+            {
+                self.best_solution = Some(results.clone());
+            }
             let update = job::Update {
                 status: job::Status::Running,
+                queue_position: None,
+                nfp_cache_stats: None,
                 nesting_solution: Some(results),
                 error: None,
             };
             (self.update_callback)(update);
+
+            if self.control.is_timed_out() {
+                break;
+            }
+        }
+
+        if self.control.is_cancelled() {
+            (self.update_callback)(job::Update {
+                status: job::Status::Failed,
+                queue_position: None,
+                nfp_cache_stats: Some(self.nfp_cache.stats()),
+                nesting_solution: self.best_solution.to_owned(),
+                error: Some(job::Error {
+                    error_type: job::ErrorType::Cancelled,
+                    message: "the job was cancelled".to_owned(),
+                }),
+            });
+            return;
         }
-        (self.update_callback)(job::Update {
-            status: job::Status::Done,
+        let error = self.control.is_timed_out().then(|| job::Error {
+            error_type: job::ErrorType::Timeout,
+            message: format!("stopped after the timeout of {}ms", self.job.timeout),
+        });
+        (self.update_callback)(self.final_update(error))
+    }
+
+    // the best solution is Done, unless it doesn't place all parts
+    fn final_update(&self, error: Option<job::Error>) -> job::Update {
+        let mut part_indices = self
+            .best_solution
+            .iter()
+            .flat_map(|solution| &solution.unplaced)
+            .map(|placement| placement.part_index)
+            .collect::<Vec<_>>();
+        if part_indices.is_empty() {
+            return job::Update {
+                status: job::Status::Done,
+                queue_position: None,
+                nfp_cache_stats: Some(self.nfp_cache.stats()),
+                nesting_solution: self.best_solution.to_owned(),
+                error,
+            };
+        }
+
+        part_indices.sort();
+        part_indices.dedup();
+        let part_indices = part_indices
+            .iter()
+            .map(|part_index| part_index.to_string())
+            .collect::<Vec<_>>();
+        job::Update {
+            status: job::Status::Failed,
+            queue_position: None,
+            nfp_cache_stats: Some(self.nfp_cache.stats()),
             nesting_solution: self.best_solution.to_owned(),
-            error: None,
-        })
+            error: Some(job::Error {
+                error_type: job::ErrorType::PartDoesNotFit,
+                message: format!(
+                    "part {} could not be placed on the sheets",
+                    part_indices.join(", ")
+                ),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::input;
+    use geo::Coord;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn control() -> Arc<JobControl> {
+        Arc::new(JobControl::new())
+    }
+
+    // runs the job and returns the status, error type and whether there is a solution of every
+    // update. The job is cancelled after cancel_after running updates.
+    fn run(input: job::Input, cancel_after: usize) -> Vec<(String, String, bool)> {
+        let updates = Rc::new(RefCell::new(vec![]));
+        let control = control();
+        let callback = {
+            let (updates, control) = (updates.clone(), control.clone());
+            move |update: job::Update| {
+                let json = serde_json::to_value(&update).unwrap();
+                updates.borrow_mut().push((
+                    json["status"].as_str().unwrap().to_owned(),
+                    json["error"]["error_type"]
+                        .as_str()
+                        .unwrap_or("")
+                        .to_owned(),
+                    !json["nesting_solution"].is_null(),
+                ));
+                if updates.borrow().len() >= cancel_after {
+                    control.cancel();
+                }
+            }
+        };
+        if cancel_after == 0 {
+            control.cancel();
+        }
+
+        let mut runner = NestingRunner::new(input, Box::new(callback), control)
+            .ok()
+            .unwrap();
+        runner.start();
+        let updates = updates.borrow().clone();
+        updates
+    }
+
+    fn last_update(input: job::Input) -> job::Update {
+        let updates = Rc::new(RefCell::new(vec![]));
+        let callback = {
+            let updates = updates.clone();
+            move |update: job::Update| updates.borrow_mut().push(update)
+        };
+        let mut runner = NestingRunner::new(input, Box::new(callback), control())
+            .ok()
+            .unwrap();
+        runner.start();
+        let last = updates.borrow_mut().pop().unwrap();
+        last
+    }
+
+    fn update(status: &str, error_type: &str, has_solution: bool) -> (String, String, bool) {
+        (status.to_owned(), error_type.to_owned(), has_solution)
+    }
+
+    #[test]
+    fn runs_until_done() {
+        let updates = run(input(vec![0., 180.]), usize::MAX);
+        assert!(updates.len() > 1);
+        assert_eq!(updates.last(), Some(&update("Done", "", true)));
+    }
+
+    #[test]
+    fn reports_the_nfp_cache_stats_at_the_end() {
+        let done = last_update(input(vec![0., 180.]));
+        let stats = done.nfp_cache_stats.unwrap();
+        assert!(stats.misses > 0 && stats.entries > 0);
+    }
+
+    #[test]
+    fn runs_simulated_annealing() {
+        let mut input = input(vec![0., 180.]);
+        input.optimizer = Some(job::OptimizerConfig {
+            algorithm: job::Algorithm::SimulatedAnnealing,
+            max_generations: Some(3),
+            ..Default::default()
+        });
+        assert_eq!(
+            run(input, usize::MAX),
+            vec![
+                update("Running", "", true),
+                update("Running", "", true),
+                update("Running", "", true),
+                update("Done", "", true)
+            ]
+        );
+    }
+
+    #[test]
+    fn fails_if_the_best_solution_does_not_place_all_parts() {
+        let mut runner = NestingRunner::new(input(vec![0.]), Box::new(|_| {}), control())
+            .ok()
+            .unwrap();
+        assert_eq!(runner.final_update(None).status, job::Status::Done);
+
+        let mut solution = runner.optimizer.next().unwrap();
+        let (placement, _, _) = solution.placements_and_location.pop().unwrap();
+        solution.unplaced.push(placement);
+        runner.best_solution = Some(solution);
+        let update = runner.final_update(None);
+        assert_eq!(update.status, job::Status::Failed);
+        let error = update.error.unwrap();
+        assert_eq!(error.error_type, job::ErrorType::PartDoesNotFit);
+        assert_eq!(error.message, "part 0 could not be placed on the sheets");
+        assert!(update.nesting_solution.is_some());
+    }
+
+    #[test]
+    fn stops_at_timeout() {
+        let mut input = input(vec![0., 180.]);
+        input.timeout = 0;
+        assert_eq!(
+            run(input, usize::MAX),
+            vec![update("Running", "", true), update("Done", "Timeout", true)]
+        );
+    }
+
+    #[test]
+    fn cancels_between_generations() {
+        assert_eq!(
+            run(input(vec![0., 180.]), 0),
+            vec![update("Failed", "Cancelled", false)]
+        );
+        assert_eq!(
+            run(input(vec![0., 180.]), 1),
+            vec![
+                update("Running", "", true),
+                update("Failed", "Cancelled", true)
+            ]
+        );
+    }
+
+    #[test]
+    fn removes_rotations_that_do_not_fit() {
+        let runner = NestingRunner::new(
+            input(vec![0., 90., 180., 270.]),
+            Box::new(|_| {}),
+            control(),
+        );
+        assert_eq!(
+            runner.ok().unwrap().nfp_cache.parts[0].rotations,
+            Rotations::angles(&[0., 180.], false)
+        );
+    }
+
+    #[test]
+    fn offsets_parts_by_half_the_tool_diameter() {
+        let mut input = input(vec![0., 90., 180., 270.]);
+        input.tool_diameter = 2.;
+        let runner = NestingRunner::new(input, Box::new(|_| {}), control())
+            .ok()
+            .unwrap();
+        let part = &runner.nfp_cache.parts[0];
+        assert_eq!(
+            part.polygon.bounding_rect(),
+            geo::Rect::new((-1., -1.), (31., 11.))
+        );
+        assert_eq!(part.rotations, Rotations::angles(&[0., 180.], false));
+    }
+
+    #[test]
+    fn fails_if_a_part_does_not_fit() {
+        let error = NestingRunner::new(input(vec![90.]), Box::new(|_| {}), control())
+            .err()
+            .unwrap();
+        assert!(matches!(error.error_type, job::ErrorType::PartDoesNotFit));
+        assert_eq!(error.message, "part 0 does not fit on any of the sheets");
+    }
+
+    #[test]
+    fn supports_rotation_steps_and_ranges() {
+        let mut steps = input(vec![]);
+        steps.parts[0].rotation_steps = Some(4);
+        let runner = NestingRunner::new(steps, Box::new(|_| {}), control());
+        assert_eq!(
+            runner.ok().unwrap().nfp_cache.parts[0].rotations,
+            Rotations::angles(&[0., 180.], false)
+        );
+
+        let mut range = input(vec![]);
+        range.parts[0].rotation_range = Some((-10., 10.));
+        let runner = NestingRunner::new(range, Box::new(|_| {}), control());
+        let rotations = &runner.ok().unwrap().nfp_cache.parts[0].rotations;
+        assert!(matches!(rotations, Rotations::Range { from, to, fitting }
+            if *from == -10. && *to == 10. && fitting.len() == 21));
+        let mut rng = rand::thread_rng();
+        for _ in 0..100 {
+            let (angle, mirrored) = rotations.other(0., false, &mut rng).unwrap();
+            assert!(!mirrored);
+            assert!((-10. ..=10.).contains(&angle));
+            assert!((angle - angle.round()).abs() < EPSILON);
+        }
+
+        let mut upright = input(vec![]);
+        upright.parts[0].rotation_range = Some((80., 100.));
+        assert!(NestingRunner::new(upright, Box::new(|_| {}), control()).is_err());
+    }
+
+    #[test]
+    fn starts_ranges_at_angles_that_fit() {
+        // the part only fits upright, a few degrees around 90
+        let range = || {
+            let mut range = input(vec![]);
+            range.parts[0].rotation_range = Some((60., 120.));
+            range.sheets[0].width = 12.;
+            range.sheets[0].length = 100.;
+            range.optimizer = Some(job::OptimizerConfig {
+                max_generations: Some(2),
+                ..Default::default()
+            });
+            range
+        };
+
+        let runner = NestingRunner::new(range(), Box::new(|_| {}), control())
+            .ok()
+            .unwrap();
+        for (angle, _) in runner.nfp_cache.parts[0].rotations.initial() {
+            assert!((85. ..=95.).contains(&angle));
+        }
+
+        let done = last_update(range());
+        assert_eq!(done.status, job::Status::Done);
+        let solution = done.nesting_solution.unwrap();
+        assert_eq!(solution.placements_and_location.len(), 2);
+    }
+
+    #[test]
+    fn nests_concave_parts_at_any_angle() {
+        let mut input = input(vec![]);
+        input.parts[0] = job::Part {
+            quantity: 4,
+            contour: vec![
+                Coord { x: 0., y: 0. },
+                Coord { x: 30., y: 0. },
+                Coord { x: 30., y: 10. },
+                Coord { x: 10., y: 10. },
+                Coord { x: 10., y: 30. },
+                Coord { x: 0., y: 30. },
+            ],
+            holes: vec![],
+            rotations: vec![],
+            rotation_range: Some((0., 360.)),
+            rotation_steps: None,
+            allow_mirror: false,
+            grain: None,
+        };
+        input.sheets[0].length = 100.;
+        input.sheets[0].width = 100.;
+        input.tool_diameter = 1.;
+        input.optimizer = Some(job::OptimizerConfig {
+            max_generations: Some(3),
+            ..Default::default()
+        });
+
+        let done = last_update(input);
+        assert_eq!(done.status, job::Status::Done);
+        let solution = done.nesting_solution.unwrap();
+        assert_eq!(solution.placements_and_location.len(), 4);
+        // one NFP per relative angle in whole degrees
+        assert!(done.nfp_cache_stats.unwrap().entries <= 360);
+    }
+
+    #[test]
+    fn restricts_rotations_to_the_grain() {
+        // the part runs along the grain when its long side runs along the length of the sheet
+        let mut input = input(vec![0., 90., 180., 270.]);
+        input.parts[0].grain = Some(0.);
+        input.sheets[0] = job::Sheet {
+            length: 40.,
+            width: 40.,
+            cost: 1.,
+            grain: Some(job::Grain::Length),
+        };
+        let runner = NestingRunner::new(input, Box::new(|_| {}), control());
+        let part = &runner.ok().unwrap().nfp_cache.parts[0];
+        assert_eq!(part.rotations, Rotations::angles(&[90., 270.], false));
+
+        let mut range = part.clone();
+        range.rotations = Rotations::range(-100., 100., false);
+        range.restrict_rotations_to_grain(&[input_sheet(job::Grain::Length)]);
+        assert_eq!(range.rotations, Rotations::angles(&[-90., 90.], false));
+
+        // only the mirrored part runs along the grain at 30 degrees
+        range.rotations = Rotations::range(0., 100., true);
+        range.allow_mirror = true;
+        range.grain = Some(30.);
+        range.restrict_rotations_to_grain(&[input_sheet(job::Grain::Width)]);
+        assert_eq!(range.rotations, Rotations::Angles(vec![(30., true)]));
+        assert!(range.fits_grain(30., true, &input_sheet(job::Grain::Width)));
+        assert!(!range.fits_grain(30., false, &input_sheet(job::Grain::Width)));
+    }
+
+    #[test]
+    fn places_parts_that_only_fit_the_grain_mirrored() {
+        let mut input = input(vec![]);
+        input.parts[0].rotation_range = Some((0., 100.));
+        input.parts[0].allow_mirror = true;
+        input.parts[0].grain = Some(30.);
+        input.sheets[0] = input_sheet(job::Grain::Width);
+        input.optimizer = Some(job::OptimizerConfig {
+            max_generations: Some(2),
+            ..Default::default()
+        });
+
+        let done = last_update(input);
+        assert_eq!(done.status, job::Status::Done);
+        let solution = done.nesting_solution.unwrap();
+        assert_eq!(solution.placements_and_location.len(), 2);
+        assert!(solution
+            .placements_and_location
+            .iter()
+            .all(|(placement, _, _)| placement.mirrored));
+    }
+
+    fn input_sheet(grain: job::Grain) -> job::Sheet {
+        job::Sheet {
+            length: 40.,
+            width: 40.,
+            cost: 1.,
+            grain: Some(grain),
+        }
     }
 }
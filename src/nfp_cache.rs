@@ -1,12 +1,262 @@
-use part::Part;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+
+use crate::job::NFPCacheStats;
+use crate::nest_polygon::{normalize_angle, NestPolygon};
+use crate::nesting_runner::NestPart;
+
+// Caches rotated parts and the NFPs between them. It is shared by the threads that pack the
+// individuals of a population, so everything is behind locks.
+//
+// NFPs are stored for the unrotated first part only, because rotating both parts by the same
+// angle rotates their NFP by that angle as well:
+//   NFP(rot_a(A), rot_b(B)) = rot_a(NFP(A, rot_b-rot_a(B)))
+// The same holds for mirroring both parts, and mirroring turns rotations the other way:
+//   NFP(mir(A), mir(B)) = mir(NFP(A, B)) and mir(rot_a(B)) = rot_-a(mir(B))
+// Swapping the parts mirrors the NFP in the origin, which is a rotation by 180 degrees:
+//   NFP(B, A) = rot_180(NFP(A, B))
+// so we only store NFPs with part_a <= part_b and only one per relative angle and mirroring.
+// Angles are keyed in microdegrees, see angle_key.
+// part_a, part_b, the angle of part_b relative to part_a and whether part_b is mirrored
+type NFPKey = (usize, usize, i64, bool);
+
+// at most this many NFPs are kept, the oldest ones are dropped first. Parts with rotation ranges
+// have many relative angles, so their NFPs would otherwise pile up.
+const MAX_ENTRIES: usize = 100_000;
+
+#[derive(Default)]
+struct Entries {
+    nfps: HashMap<NFPKey, Arc<NestPolygon>>,
+    // keys from oldest to newest
+    order: VecDeque<NFPKey>,
+}
 
 pub struct NFPCache {
-    pub parts: Vec<Part>,
-    //  cache: HashMap<...>,
+    pub parts: Vec<NestPart>,
+    max_entries: usize,
+    rotated_parts: RwLock<HashMap<(usize, i64, bool), Arc<NestPolygon>>>,
+    cache: RwLock<Entries>,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
 }
 
 impl NFPCache {
-    // fn new() {}
-    // fn get_nfp(ix_a, rot_a, ix_b, rot_b) {}
-    // fn calc_nfp(ix_a, rot_a, ix_b, rot_b) {}
+    pub fn new(parts: Vec<NestPart>) -> NFPCache {
+        NFPCache {
+            parts,
+            max_entries: MAX_ENTRIES,
+            rotated_parts: RwLock::new(HashMap::new()),
+            cache: RwLock::new(Entries::default()),
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+        }
+    }
+
+    // the part mirrored if requested, then rotated, like a placement
+    pub fn get_polygon(&self, part_index: usize, angle: f64, mirrored: bool) -> Arc<NestPolygon> {
+        let key = (part_index, angle_key(angle), mirrored);
+        if let Some(polygon) = self.rotated_parts.read().unwrap().get(&key) {
+            return polygon.clone();
+        }
+
+        let polygon = &self.parts[part_index].polygon;
+        let polygon = Arc::new(if mirrored {
+            polygon.mirrored().rotated(angle)
+        } else {
+            polygon.rotated(angle)
+        });
+        self.rotated_parts
+            .write()
+            .unwrap()
+            .entry(key)
+            .or_insert(polygon)
+            .clone()
+    }
+
+    // returns the NFP of part_b orbiting part_a, see NestPolygon::no_fit_polygon
+    pub fn get_nfp(
+        &self,
+        part_a: usize,
+        rot_a: f64,
+        mirrored_a: bool,
+        part_b: usize,
+        rot_b: f64,
+        mirrored_b: bool,
+    ) -> NestPolygon {
+        // the cached NFP has to be transformed like the first part of the key
+        let swapped = part_a > part_b;
+        let ((first, rot_first, mirrored_first), (second, rot_second, mirrored_second)) = if swapped
+        {
+            ((part_b, rot_b, mirrored_b), (part_a, rot_a, mirrored_a))
+        } else {
+            ((part_a, rot_a, mirrored_a), (part_b, rot_b, mirrored_b))
+        };
+        let key = if mirrored_first {
+            (
+                first,
+                second,
+                angle_key(rot_first - rot_second),
+                !mirrored_second,
+            )
+        } else {
+            (
+                first,
+                second,
+                angle_key(rot_second - rot_first),
+                mirrored_second,
+            )
+        };
+        let rotation = if swapped { rot_first + 180. } else { rot_first };
+
+        let cached = self.cache.read().unwrap().nfps.get(&key).cloned();
+        let nfp = match cached {
+            Some(nfp) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                nfp
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                self.calc_nfp(key)
+            }
+        };
+
+        match (mirrored_first, angle_key(rotation) == 0) {
+            (false, true) => nfp.as_ref().clone(),
+            (false, false) => nfp.rotated(rotation),
+            (true, _) => nfp.mirrored().rotated(rotation),
+        }
+    }
+
+    pub fn stats(&self) -> NFPCacheStats {
+        NFPCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: self.cache.read().unwrap().nfps.len(),
+        }
+    }
+
+    // calculates the NFP without holding the lock, so other threads can continue. They might
+    // calculate the same NFP in the meantime, in which case we keep the first one.
+    fn calc_nfp(&self, key: NFPKey) -> Arc<NestPolygon> {
+        let (part_a, part_b, relative_angle, mirrored) = key;
+        let nfp = Arc::new(
+            self.get_polygon(part_a, 0., false)
+                .no_fit_polygon(&self.get_polygon(part_b, relative_angle as f64 / 1e6, mirrored)),
+        );
+        let mut cache = self.cache.write().unwrap();
+        if let Some(nfp) = cache.nfps.get(&key) {
+            return nfp.clone();
+        }
+        cache.nfps.insert(key, nfp.clone());
+        cache.order.push_back(key);
+        while cache.order.len() > self.max_entries {
+            let oldest = cache.order.pop_front().unwrap();
+            cache.nfps.remove(&oldest);
+        }
+        nfp
+    }
+}
+
+// the angle in microdegrees in 0..360 degrees, so angles that only differ by rounding errors share
+// their cache entries
+fn angle_key(angle: f64) -> i64 {
+    (normalize_angle(angle) * 1e6).round() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nesting_runner::Rotations;
+    use geo::Coord;
+
+    fn parts() -> Vec<NestPart> {
+        let triangle = NestPolygon::new(vec![
+            Coord { x: 0., y: 0. },
+            Coord { x: 20., y: 0. },
+            Coord { x: 5., y: 10. },
+        ]);
+        let l_bracket = NestPolygon::new(vec![
+            Coord { x: 0., y: 0. },
+            Coord { x: 30., y: 0. },
+            Coord { x: 30., y: 10. },
+            Coord { x: 10., y: 10. },
+            Coord { x: 10., y: 30. },
+            Coord { x: 0., y: 30. },
+        ]);
+        [triangle, l_bracket]
+            .into_iter()
+            .map(|polygon| NestPart {
+                quantity: 1,
+                area: polygon.area(),
+                polygon,
+                rotations: Rotations::angles(&[0., 90., 180., 270.], true),
+                allow_mirror: true,
+                grain: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn uses_symmetries() {
+        let cache = NFPCache::new(parts());
+        for (part_a, part_b) in [(0, 1), (1, 0), (1, 1)] {
+            for rot_a in [0., 90., 180., 270.] {
+                for rot_b in [0., 90., 180., 270.] {
+                    let expected = cache.parts[part_a]
+                        .polygon
+                        .rotated(rot_a)
+                        .no_fit_polygon(&cache.parts[part_b].polygon.rotated(rot_b));
+                    let nfp = cache.get_nfp(part_a, rot_a, false, part_b, rot_b, false);
+                    assert_eq!(nfp.bounding_rect(), expected.bounding_rect());
+                }
+            }
+        }
+
+        // (0, 1) and (1, 0) share their NFPs
+        assert_eq!(
+            cache.stats(),
+            NFPCacheStats {
+                hits: 40,
+                misses: 8,
+                entries: 8,
+            }
+        );
+    }
+
+    #[test]
+    fn drops_the_oldest_nfps() {
+        let mut cache = NFPCache::new(parts());
+        cache.max_entries = 2;
+        for rot_b in [0., 90., 180.] {
+            cache.get_nfp(0, 0., false, 1, rot_b, false);
+        }
+        cache.get_nfp(0, 0., false, 1, 180., false);
+        cache.get_nfp(0, 0., false, 1, 0., false);
+        assert_eq!(
+            cache.stats(),
+            NFPCacheStats {
+                hits: 1,
+                misses: 4,
+                entries: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn mirrors_parts() {
+        let cache = NFPCache::new(parts());
+        for (part_a, part_b) in [(0, 1), (1, 0), (1, 1)] {
+            for (rot_a, rot_b) in [(0., 0.), (90., 0.), (0., 270.), (180., 90.)] {
+                for (mirrored_a, mirrored_b) in [(true, false), (false, true), (true, true)] {
+                    let expected = cache
+                        .get_polygon(part_a, rot_a, mirrored_a)
+                        .no_fit_polygon(&cache.get_polygon(part_b, rot_b, mirrored_b));
+                    let nfp = cache.get_nfp(part_a, rot_a, mirrored_a, part_b, rot_b, mirrored_b);
+                    assert_eq!(nfp.bounding_rect(), expected.bounding_rect());
+                    assert!((nfp.area() - expected.area()).abs() < 1e-6);
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,58 @@
+// the search strategies that NestingRunner can use to find a good placement sequence. Each
+// iteration of an optimizer, e.g. a generation, yields its best solution, and the search is done
+// when it yields None. When the job control says the job should stop, optimizers cut their
+// iteration short and yield the best solution they found in it.
+
+use std::sync::Arc;
+
+use crate::genetic_algorithm::Population;
+use crate::islands::Islands;
+use crate::job;
+use crate::job_control::JobControl;
+use crate::nfp_cache::NFPCache;
+use crate::simulated_annealing::SimulatedAnnealing;
+
+// seeds are sent to the host as JSON numbers, which JavaScript only represents exactly up to 2^53
+pub const MAX_SEED: u64 = (1 << 53) - 1;
+
+pub trait Optimizer: Iterator<Item = job::GenerationResult> {}
+
+impl Optimizer for Population {}
+
+impl Optimizer for Islands {}
+
+impl Optimizer for SimulatedAnnealing {}
+
+pub fn optimizer(
+    nfp_cache: Arc<NFPCache>,
+    sheets: Vec<job::Sheet>,
+    config: job::OptimizerConfig,
+    control: Arc<JobControl>,
+) -> Box<dyn Optimizer> {
+    match config.algorithm {
+        // a single island is a single population
+        job::Algorithm::Genetic => Box::new(Islands::new(nfp_cache, sheets, config, control)),
+        job::Algorithm::SimulatedAnnealing => {
+            Box::new(SimulatedAnnealing::new(nfp_cache, sheets, config, control))
+        }
+    }
+}
+
+// the seed of a job that doesn't get one, which the host can pass back to reproduce the job
+pub fn random_seed() -> u64 {
+    rand::random::<u64>() & MAX_SEED
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn draws_seeds_that_survive_json() {
+        for _ in 0..1000 {
+            let seed = random_seed();
+            let json = serde_json::to_string(&seed).unwrap();
+            assert_eq!(json.parse::<f64>().unwrap() as u64, seed);
+        }
+    }
+}
@@ -1,39 +1,472 @@
-// todo: on new assert quantity > 0
-// #[derive(Clone, Debug)]
-// pub struct Placement {
-//     pub part_index: usize,
-//     pub nth_part: u32,
-//     pub angle: f64,
-// }
+use geo::{Coord, Rect};
 
-use crate::job::Placement;
+use crate::job::{self, Placement};
+use crate::nest_polygon::{segment_intersection, NestPolygon, EPSILON};
+use crate::nesting_runner::NestPart;
+use crate::nfp_cache::NFPCache;
 
 #[derive(Clone)]
 pub struct PlacementSequence {
     pub placements: Vec<Placement>,
 }
 
+// fitness is the cost of the used sheets, where only the used length of the last sheet counts.
+// Lower is better.
 pub struct PackingResult {
     pub fitness: f64,
-    pub placed_at: Vec<geo::Coord>,
+    // for every placement the index of the sheet it is placed on and its location
+    pub placed_at: Vec<Option<(usize, geo::Coord)>>,
+    // for every sheet that is used the index of its stock in job::Input::sheets
+    pub sheets: Vec<usize>,
+    pub last_sheet_used_length: f64,
+}
+
+// a sheet that has been opened while packing, with the parts placed on it so far
+struct OpenSheet {
+    stock: usize,
+    placed: Vec<(Placement, Coord)>,
+    used_length: f64,
+}
+
+impl PackingResult {
+    // reports the packing along with the material it uses. Only the used length of the last
+    // sheet counts as consumed, the rest of it can be used for another job.
+    pub fn generation_result(
+        &self,
+        sequence: &PlacementSequence,
+        parts: &[NestPart],
+        stock: &[job::Sheet],
+        seed: u64,
+    ) -> job::GenerationResult {
+        let mut placed_areas = vec![0.; self.sheets.len()];
+        for (placement, position) in sequence.placements.iter().zip(self.placed_at.iter()) {
+            if let Some((sheet_index, _)) = position {
+                placed_areas[*sheet_index] += parts[placement.part_index].area;
+            }
+        }
+
+        let sheet = |sheet_index: usize| &stock[self.sheets[sheet_index]];
+        let sheet_area = |sheet: &job::Sheet| sheet.width as f64 * sheet.length as f64;
+
+        let mut consumed_area: f64 = (0..self.sheets.len())
+            .map(|sheet_index| sheet_area(sheet(sheet_index)))
+            .sum();
+        let mut last_sheet_left_over = 0.;
+        if let Some(last) = self.sheets.len().checked_sub(1) {
+            let sheet = sheet(last);
+            last_sheet_left_over = sheet.length as f64 - self.last_sheet_used_length;
+            consumed_area -= sheet.width as f64 * last_sheet_left_over;
+        }
+        let placed_area: f64 = placed_areas.iter().sum();
+
+        job::GenerationResult {
+            fitness: self.fitness,
+            seed,
+            sheet_count: self.sheets.len() as i32,
+            last_sheet_left_over: last_sheet_left_over as i32,
+            last_sheet_used_length: self.last_sheet_used_length as f32,
+            cut_loss_ratio: if consumed_area > 0. {
+                (1. - placed_area / consumed_area) as f32
+            } else {
+                0.
+            },
+            sheet_utilisation: placed_areas
+                .iter()
+                .enumerate()
+                .map(|(sheet_index, area)| (area / sheet_area(sheet(sheet_index))) as f32)
+                .collect(),
+            total_cost: (0..self.sheets.len())
+                .map(|sheet_index| sheet(sheet_index).cost)
+                .sum(),
+            sheets: self.sheets.to_owned(),
+            placements_and_location: sequence
+                .placements
+                .iter()
+                .copied()
+                .zip(self.placed_at.iter().copied())
+                .filter_map(|(placement, position)| {
+                    let (sheet_index, location) = position?;
+                    Some((placement, sheet_index, location))
+                })
+                .collect::<Vec<_>>(),
+            unplaced: sequence
+                .placements
+                .iter()
+                .zip(self.placed_at.iter())
+                .filter(|(_, position)| position.is_none())
+                .map(|(placement, _)| *placement)
+                .collect(),
+        }
+    }
 }
 
 impl PlacementSequence {
-    pub fn pack(&self) -> PackingResult {
+    // places the parts one by one at the lowest, then leftmost position of the first sheet where
+    // they don't overlap the parts that were placed before. A sheet spans x in 0..width and y in
+    // 0..length, so sheets are filled up along their length. When a part doesn't fit on any of
+    // the opened sheets a new sheet is opened, see choose_stock.
+    pub fn pack(&self, nfp_cache: &NFPCache, stock: &[job::Sheet]) -> PackingResult {
+        let mut sheets = Vec::<OpenSheet>::new();
+        let mut placed_at = Vec::with_capacity(self.placements.len());
+        let mut unplaced = 0;
+        let mut remaining_area: f64 = self
+            .placements
+            .iter()
+            .map(|p| nfp_cache.parts[p.part_index].polygon.area())
+            .sum();
+
+        for placement in self.placements.iter() {
+            let polygon =
+                nfp_cache.get_polygon(placement.part_index, placement.angle, placement.mirrored);
+
+            let part = &nfp_cache.parts[placement.part_index];
+            let fits_grain =
+                |sheet: &job::Sheet| part.fits_grain(placement.angle, placement.mirrored, sheet);
+
+            let mut position = sheets
+                .iter()
+                .enumerate()
+                .filter(|(_, sheet)| fits_grain(&stock[sheet.stock]))
+                .find_map(|(sheet_index, sheet)| {
+                    sheet
+                        .position(nfp_cache, &stock[sheet.stock], placement, &polygon)
+                        .map(|location| (sheet_index, location))
+                });
+
+            if position.is_none() {
+                if let Some(stock_index) = choose_stock(stock, &polygon, remaining_area, fits_grain)
+                {
+                    let sheet = OpenSheet {
+                        stock: stock_index,
+                        placed: vec![],
+                        used_length: 0.,
+                    };
+                    position = sheet
+                        .position(nfp_cache, &stock[stock_index], placement, &polygon)
+                        .map(|location| (sheets.len(), location));
+                    sheets.push(sheet);
+                }
+            }
+
+            match position {
+                Some((sheet_index, location)) => {
+                    let sheet = &mut sheets[sheet_index];
+                    sheet.used_length = sheet
+                        .used_length
+                        .max(location.y + polygon.bounding_rect().max().y);
+                    sheet.placed.push((*placement, location));
+                }
+                None => unplaced += 1,
+            }
+            placed_at.push(position);
+            remaining_area -= polygon.area();
+        }
+
+        // the part of the last sheet that isn't used can be used for another job
+        let last_sheet_used_length = sheets.last().map_or(0., |sheet| sheet.used_length);
+        let mut fitness: f64 = sheets
+            .iter()
+            .map(|sheet| stock[sheet.stock].cost as f64)
+            .sum();
+        if let Some(last) = sheets.last() {
+            let sheet = &stock[last.stock];
+            fitness -= sheet.cost as f64 * (1. - last.used_length / sheet.length as f64);
+        }
+        let max_cost = stock
+            .iter()
+            .map(|sheet| sheet.cost as f64)
+            .fold(0., f64::max);
+        fitness += unplaced as f64 * max_cost;
+
         PackingResult {
-            fitness: 123.,
-            placed_at: vec![
-                geo::Coord { x: 0., y: 0. },
-                geo::Coord { x: 0., y: 0. },
-                geo::Coord { x: 0., y: 0. },
-                geo::Coord { x: 0., y: 0. },
-                geo::Coord { x: 0., y: 0. },
-                geo::Coord { x: 0., y: 0. },
-                geo::Coord { x: 0., y: 0. },
-                geo::Coord { x: 0., y: 0. },
-                geo::Coord { x: 0., y: 0. },
-                geo::Coord { x: 0., y: 0. },
-            ],
+            fitness,
+            placed_at,
+            sheets: sheets.iter().map(|sheet| sheet.stock).collect(),
+            last_sheet_used_length,
+        }
+    }
+}
+
+impl OpenSheet {
+    fn position(
+        &self,
+        nfp_cache: &NFPCache,
+        sheet: &job::Sheet,
+        placement: &Placement,
+        polygon: &NestPolygon,
+    ) -> Option<Coord> {
+        let ifp = polygon.inner_fit_rect(sheet.width as f64, sheet.length as f64)?;
+        let nfps = self
+            .placed
+            .iter()
+            .map(|(placed, location)| {
+                nfp_cache
+                    .get_nfp(
+                        placed.part_index,
+                        placed.angle,
+                        placed.mirrored,
+                        placement.part_index,
+                        placement.angle,
+                        placement.mirrored,
+                    )
+                    .translated(*location)
+            })
+            .collect::<Vec<_>>();
+        bottom_left_position(&ifp, &nfps)
+    }
+}
+
+// Picks the stock for a new sheet that the part fits on, along its grain, with the lowest cost per area that will
+// be filled. When the remaining parts don't fill a whole sheet only their area counts, so a
+// smaller sheet can be cheaper even if its cost per area is higher.
+fn choose_stock(
+    stock: &[job::Sheet],
+    polygon: &NestPolygon,
+    remaining_area: f64,
+    fits_grain: impl Fn(&job::Sheet) -> bool,
+) -> Option<usize> {
+    stock
+        .iter()
+        .enumerate()
+        .filter(|(_, sheet)| {
+            fits_grain(sheet)
+                && polygon
+                    .inner_fit_rect(sheet.width as f64, sheet.length as f64)
+                    .is_some()
+        })
+        .map(|(stock_index, sheet)| {
+            let area = sheet.width as f64 * sheet.length as f64;
+            (stock_index, sheet.cost as f64 / area.min(remaining_area))
+        })
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(stock_index, _)| stock_index)
+}
+
+// Finds the lowest, then leftmost translation inside the inner fit rectangle that is not strictly
+// inside one of the no fit polygons. Such a position is always at a corner of the feasible region,
+// so it suffices to check the corners of the rectangle, the vertices of the NFPs and the points
+// where their edges cross.
+fn bottom_left_position(ifp: &Rect, nfps: &[NestPolygon]) -> Option<Coord> {
+    let ifp_edges = ifp.to_lines();
+    let mut candidates: Vec<Coord> = ifp_edges.iter().map(|line| line.start).collect();
+
+    let bounds = nfps
+        .iter()
+        .map(|nfp| nfp.bounding_rect())
+        .collect::<Vec<_>>();
+    for (i, nfp) in nfps.iter().enumerate() {
+        if !overlaps(&bounds[i], ifp) {
+            continue;
         }
+        for (a1, a2) in nfp.edges() {
+            candidates.push(a1);
+            for line in ifp_edges.iter() {
+                candidates.extend(segment_intersection(a1, a2, line.start, line.end));
+            }
+            for (j, other) in nfps.iter().enumerate().skip(i + 1) {
+                if !overlaps(&bounds[i], &bounds[j]) {
+                    continue;
+                }
+                for (b1, b2) in other.edges() {
+                    candidates.extend(segment_intersection(a1, a2, b1, b2));
+                }
+            }
+        }
+    }
+
+    candidates.retain(|c| {
+        c.x >= ifp.min().x - EPSILON
+            && c.x <= ifp.max().x + EPSILON
+            && c.y >= ifp.min().y - EPSILON
+            && c.y <= ifp.max().y + EPSILON
+    });
+    candidates.sort_by(|a, b| (a.y, a.x).partial_cmp(&(b.y, b.x)).unwrap());
+
+    candidates
+        .into_iter()
+        .map(|c| Coord {
+            x: c.x.clamp(ifp.min().x, ifp.max().x),
+            y: c.y.clamp(ifp.min().y, ifp.max().y),
+        })
+        .find(|c| {
+            nfps.iter()
+                .zip(bounds.iter())
+                .all(|(nfp, b)| !contains(b, *c) || !nfp.contains_strictly(*c))
+        })
+}
+
+fn overlaps(a: &Rect, b: &Rect) -> bool {
+    a.min().x <= b.max().x + EPSILON
+        && b.min().x <= a.max().x + EPSILON
+        && a.min().y <= b.max().y + EPSILON
+        && b.min().y <= a.max().y + EPSILON
+}
+
+fn contains(rect: &Rect, c: Coord) -> bool {
+    overlaps(rect, &Rect::new(c, c))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nesting_runner::{NestPart, Rotations};
+    use crate::test_fixtures::{sheet, square_part};
+
+    fn pack(parts: Vec<NestPart>, sheets: Vec<job::Sheet>) -> PackingResult {
+        sequence(&parts).pack(&NFPCache::new(parts), &sheets)
+    }
+
+    fn sequence(parts: &[NestPart]) -> PlacementSequence {
+        let mut placements = vec![];
+        for (part_index, part) in parts.iter().enumerate() {
+            let (angle, mirrored) = part.rotations.initial()[0];
+            for nth_part in 0..part.quantity {
+                placements.push(Placement {
+                    part_index,
+                    nth_part,
+                    angle,
+                    mirrored,
+                });
+            }
+        }
+        PlacementSequence { placements }
+    }
+
+    fn at(sheet_index: usize, x: f64, y: f64) -> Option<(usize, Coord)> {
+        Some((sheet_index, Coord { x, y }))
+    }
+
+    #[test]
+    fn fills_rows_bottom_left_first() {
+        let parts = vec![square_part(10., 3)];
+        let result = pack(parts, vec![sheet(20., 40., 1.)]);
+
+        assert_eq!(
+            result.placed_at,
+            vec![at(0, 0., 0.), at(0, 10., 0.), at(0, 0., 10.)]
+        );
+        assert_eq!(result.fitness, 0.5);
+        assert_eq!(result.last_sheet_used_length, 20.);
+    }
+
+    #[test]
+    fn fills_gaps_next_to_larger_parts() {
+        let parts = vec![square_part(20., 1), square_part(10., 2)];
+        let result = pack(parts, vec![sheet(30., 40., 1.)]);
+
+        assert_eq!(
+            result.placed_at,
+            vec![at(0, 0., 0.), at(0, 20., 0.), at(0, 20., 10.)]
+        );
+        assert_eq!(result.fitness, 0.5);
+    }
+
+    #[test]
+    fn fills_concave_corners() {
+        let l_bracket = NestPart {
+            quantity: 1,
+            area: 500.,
+            polygon: NestPolygon::new(vec![
+                Coord { x: 0., y: 0. },
+                Coord { x: 30., y: 0. },
+                Coord { x: 30., y: 10. },
+                Coord { x: 10., y: 10. },
+                Coord { x: 10., y: 30. },
+                Coord { x: 0., y: 30. },
+            ]),
+            rotations: Rotations::angles(&[0.], false),
+            allow_mirror: false,
+            grain: None,
+        };
+        let parts = vec![l_bracket, square_part(10., 1)];
+        let result = pack(parts, vec![sheet(30., 40., 1.)]);
+
+        assert_eq!(result.placed_at[1], at(0, 10., 10.));
+        assert_eq!(result.fitness, 0.75);
+    }
+
+    #[test]
+    fn places_parts_in_holes() {
+        let frame = NestPart {
+            quantity: 1,
+            area: 800.,
+            polygon: NestPolygon::with_holes(
+                vec![
+                    Coord { x: 0., y: 0. },
+                    Coord { x: 30., y: 0. },
+                    Coord { x: 30., y: 30. },
+                    Coord { x: 0., y: 30. },
+                ],
+                vec![vec![
+                    Coord { x: 10., y: 10. },
+                    Coord { x: 20., y: 10. },
+                    Coord { x: 20., y: 20. },
+                    Coord { x: 10., y: 20. },
+                ]],
+            ),
+            rotations: Rotations::angles(&[0.], false),
+            allow_mirror: false,
+            grain: None,
+        };
+        let parts = vec![frame, square_part(5., 5)];
+        let result = pack(parts, vec![sheet(30., 40., 1.)]);
+
+        assert_eq!(
+            result.placed_at,
+            vec![
+                at(0, 0., 0.),
+                at(0, 10., 10.),
+                at(0, 15., 10.),
+                at(0, 10., 15.),
+                at(0, 15., 15.),
+                at(0, 0., 30.),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_unplaced_parts() {
+        let parts = vec![square_part(50., 1), square_part(10., 1)];
+        let sequence = sequence(&parts);
+        let result = sequence.pack(&NFPCache::new(parts.clone()), &[sheet(20., 40., 1.)]);
+        let result = result.generation_result(&sequence, &parts, &[sheet(20., 40., 1.)], 0);
+
+        assert_eq!(result.placements_and_location.len(), 1);
+        assert_eq!(result.unplaced.len(), 1);
+        assert_eq!(result.unplaced[0].part_index, 0);
+    }
+
+    #[test]
+    fn opens_new_sheets() {
+        let parts = vec![square_part(20., 1), square_part(10., 3)];
+        let result = pack(parts, vec![sheet(20., 20., 4.)]);
+
+        assert_eq!(
+            result.placed_at,
+            vec![at(0, 0., 0.), at(1, 0., 0.), at(1, 10., 0.), at(1, 0., 10.)]
+        );
+        assert_eq!(result.sheets, vec![0, 0]);
+        assert_eq!(result.fitness, 8.);
+    }
+
+    #[test]
+    fn backfills_earlier_sheets() {
+        let parts = vec![square_part(20., 1), square_part(15., 1), square_part(5., 1)];
+        let result = pack(parts, vec![sheet(20., 30., 3.)]);
+
+        assert_eq!(result.placed_at[2], at(0, 0., 20.));
+        assert_eq!(result.sheets, vec![0, 0]);
+        assert_eq!(result.fitness, 4.5);
+    }
+
+    #[test]
+    fn chooses_stock_by_cost_of_the_filled_area() {
+        let stock = vec![sheet(10., 10., 1.), sheet(100., 100., 50.)];
+
+        let result = pack(vec![square_part(10., 20)], stock.clone());
+        assert!(result.sheets.iter().all(|&stock_index| stock_index == 0));
+
+        let result = pack(vec![square_part(10., 100)], stock);
+        assert_eq!(result.sheets, vec![1]);
     }
 }
@@ -0,0 +1,150 @@
+// strategies that select the parents of the next generation. They get the fitnesses of the
+// individuals sorted from fittest to least fit, where lower fitness is better, and return the
+// index of the selected individual.
+
+use rand::{Rng, RngCore};
+
+use crate::job;
+
+pub trait Selection {
+    fn select(&self, fitnesses: &[f64], rng: &mut dyn RngCore) -> usize;
+}
+
+pub fn strategy(selection: job::Selection) -> Box<dyn Selection + Send + Sync> {
+    match selection {
+        job::Selection::LinearRank { pressure } => Box::new(LinearRank { pressure }),
+        job::Selection::Tournament { size } => Box::new(Tournament { size }),
+        job::Selection::Roulette => Box::new(Roulette),
+    }
+}
+
+// the chance of being selected drops linearly with the rank. The fittest individual is selected
+// pressure times as often as an average one, which is from 1 (uniform) to 2 (the least fit one is
+// never selected).
+pub struct LinearRank {
+    pub pressure: f64,
+}
+
+// the fittest of size randomly picked individuals
+pub struct Tournament {
+    pub size: usize,
+}
+
+// the chance of being selected is proportional to how much fitter an individual is than the least
+// fit one
+pub struct Roulette;
+
+impl Selection for LinearRank {
+    fn select(&self, fitnesses: &[f64], rng: &mut dyn RngCore) -> usize {
+        let n = fitnesses.len();
+        if n < 2 {
+            return 0;
+        }
+        let chance = |rank: usize| {
+            (self.pressure - (2. * self.pressure - 2.) * rank as f64 / (n - 1) as f64) / n as f64
+        };
+        spin(n, chance, rng.gen())
+    }
+}
+
+impl Selection for Tournament {
+    fn select(&self, fitnesses: &[f64], rng: &mut dyn RngCore) -> usize {
+        // the fitnesses are sorted, so the lowest index is the fittest
+        (0..self.size.max(1))
+            .map(|_| rng.gen_range(0..fitnesses.len()))
+            .min()
+            .unwrap()
+    }
+}
+
+impl Selection for Roulette {
+    fn select(&self, fitnesses: &[f64], rng: &mut dyn RngCore) -> usize {
+        let least_fit = fitnesses.iter().copied().fold(f64::MIN, f64::max);
+        let total: f64 = fitnesses.iter().map(|fitness| least_fit - fitness).sum();
+        if total.is_nan() || total <= 0. {
+            return rng.gen_range(0..fitnesses.len());
+        }
+        spin(
+            fitnesses.len(),
+            |i| (least_fit - fitnesses[i]) / total,
+            rng.gen(),
+        )
+    }
+}
+
+// returns the index at which the cumulative chance passes the random number in 0..1
+fn spin(n: usize, chance: impl Fn(usize) -> f64, random: f64) -> usize {
+    let mut cumulative = 0.;
+    for i in 0..n {
+        cumulative += chance(i);
+        if random < cumulative {
+            return i;
+        }
+    }
+    // rounding errors can leave the total just below 1
+    (0..n).rev().find(|&i| chance(i) > 0.).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    const SAMPLES: usize = 100_000;
+
+    // how often each individual of a population of 5 is selected
+    fn shares(selection: &dyn Selection) -> Vec<f64> {
+        let fitnesses = [1., 2., 3., 4., 5.];
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut counts = [0; 5];
+        for _ in 0..SAMPLES {
+            counts[selection.select(&fitnesses, &mut rng)] += 1;
+        }
+        counts
+            .iter()
+            .map(|&count| count as f64 / SAMPLES as f64)
+            .collect()
+    }
+
+    fn assert_shares(selection: &dyn Selection, expected: [f64; 5]) {
+        let shares = shares(selection);
+        for (share, expected) in shares.iter().zip(expected) {
+            assert!(
+                (share - expected).abs() < 0.01,
+                "{:?} instead of {:?}",
+                shares,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn linear_rank_selection() {
+        assert_shares(&LinearRank { pressure: 2. }, [0.4, 0.3, 0.2, 0.1, 0.]);
+        assert_shares(&LinearRank { pressure: 1.5 }, [0.3, 0.25, 0.2, 0.15, 0.1]);
+        assert_shares(&LinearRank { pressure: 1. }, [0.2; 5]);
+    }
+
+    #[test]
+    fn tournament_selection() {
+        // the least fit of 5 wins a tournament of 2 only if it's picked twice
+        assert_shares(
+            &Tournament { size: 2 },
+            [9. / 25., 7. / 25., 5. / 25., 3. / 25., 1. / 25.],
+        );
+        assert_shares(&Tournament { size: 1 }, [0.2; 5]);
+        assert!(shares(&Tournament { size: 4 })[0] > shares(&Tournament { size: 2 })[0]);
+    }
+
+    #[test]
+    fn roulette_selection() {
+        assert_shares(&Roulette, [0.4, 0.3, 0.2, 0.1, 0.]);
+
+        let mut rng = StdRng::seed_from_u64(7);
+        let selected = (0..100)
+            .map(|_| Roulette.select(&[3., 3.], &mut rng))
+            .collect::<Vec<_>>();
+        assert!(selected.contains(&0) && selected.contains(&1));
+    }
+}
@@ -0,0 +1,245 @@
+// simulated annealing moves a single placement sequence to random neighbours, see
+// mutation::neighbour. Better neighbours are always accepted, worse ones with a chance that drops
+// as the temperature cools down, so the search can leave local optima early on and settles down
+// later. Every temperature step is reported like a generation, with the best solution so far.
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::sync::Arc;
+
+use crate::genetic_algorithm::part_orderings;
+use crate::job;
+use crate::job_control::JobControl;
+use crate::mutation;
+use crate::nfp_cache::NFPCache;
+use crate::optimizer;
+use crate::packing::PlacementSequence;
+
+pub struct SimulatedAnnealing {
+    nfp_cache: Arc<NFPCache>,
+    sheets: Vec<job::Sheet>,
+    config: job::OptimizerConfig,
+    control: Arc<JobControl>,
+    seed: u64,
+    rng: StdRng,
+    current: PlacementSequence,
+    current_fitness: f64,
+    best: job::GenerationResult,
+    pub generation: usize,
+    pub last_improvement: usize,
+}
+
+impl SimulatedAnnealing {
+    // starts from the parts sorted by descending area, each at its first rotation and mirroring
+    pub fn new(
+        nfp_cache: Arc<NFPCache>,
+        sheets: Vec<job::Sheet>,
+        config: job::OptimizerConfig,
+        control: Arc<JobControl>,
+    ) -> Self {
+        let seed = config.seed.unwrap_or_else(optimizer::random_seed);
+        let parts = &nfp_cache.parts;
+        let mut placements = Vec::<job::Placement>::new();
+        for &part_index in &part_orderings(parts)[0] {
+            let part = &parts[part_index];
+            let (angle, mirrored) = part.rotations.initial()[0];
+            for nth_part in 0..part.quantity {
+                placements.push(job::Placement {
+                    part_index,
+                    nth_part,
+                    angle,
+                    mirrored,
+                });
+            }
+        }
+        let current = PlacementSequence { placements };
+        let result = current.pack(&nfp_cache, &sheets);
+        let best = result.generation_result(&current, parts, &sheets, seed);
+
+        SimulatedAnnealing {
+            current_fitness: result.fitness,
+            current,
+            best,
+            rng: StdRng::seed_from_u64(seed),
+            seed,
+            nfp_cache,
+            sheets,
+            config,
+            control,
+            generation: 0,
+            last_improvement: 0,
+        }
+    }
+
+    fn temperature(&self) -> f64 {
+        let annealing = &self.config.annealing;
+        let steps = self.generation as f64;
+        match annealing.cooling {
+            job::Cooling::Geometric { factor } => {
+                annealing.initial_temperature * factor.powf(steps)
+            }
+            job::Cooling::Linear { step } => annealing.initial_temperature - step * steps,
+            job::Cooling::Logarithmic => {
+                annealing.initial_temperature / (std::f64::consts::E + steps).ln()
+            }
+        }
+    }
+
+    // the chance of moving to a neighbour that is worse by delta, relative to the current
+    // fitness, see job::AnnealingConfig
+    fn accepts(&mut self, fitness: f64, temperature: f64) -> bool {
+        if fitness <= self.current_fitness {
+            return true;
+        }
+        let delta = (fitness - self.current_fitness) / self.current_fitness.abs().max(f64::EPSILON);
+        self.rng.gen::<f64>() < (-delta / temperature).exp()
+    }
+}
+
+impl Iterator for SimulatedAnnealing {
+    type Item = job::GenerationResult;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let temperature = self.temperature();
+        if temperature <= self.config.annealing.final_temperature
+            || self
+                .config
+                .max_generations
+                .is_some_and(|max_generations| self.generation >= max_generations)
+        {
+            return None;
+        }
+
+        let mut improved = false;
+        for _ in 0..self.config.annealing.moves_per_temperature {
+            // the best solution so far is reported once more, see optimizer
+            if self.control.should_stop() {
+                return Some(self.best.clone());
+            }
+            let mut placements = self.current.placements.to_owned();
+            mutation::neighbour(
+                &mut placements,
+                &self.config.mutation,
+                &self.nfp_cache.parts,
+                &mut self.rng,
+            );
+            let neighbour = PlacementSequence { placements };
+            let result = neighbour.pack(&self.nfp_cache, &self.sheets);
+
+            if result.fitness < self.best.fitness {
+                improved = true;
+                self.best = result.generation_result(
+                    &neighbour,
+                    &self.nfp_cache.parts,
+                    &self.sheets,
+                    self.seed,
+                );
+            }
+            if self.accepts(result.fitness, temperature) {
+                self.current = neighbour;
+                self.current_fitness = result.fitness;
+            }
+        }
+
+        // stop if the best solution didn't improve for a few temperatures, like a population
+        self.generation += 1;
+        if improved {
+            self.last_improvement = 0;
+        } else {
+            self.last_improvement += 1;
+        }
+        if self.last_improvement >= self.config.stall_limit {
+            return None;
+        }
+
+        Some(self.best.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{l_brackets, long_sheet};
+
+    fn annealing(config: job::OptimizerConfig) -> SimulatedAnnealing {
+        SimulatedAnnealing::new(
+            l_brackets(4, true),
+            long_sheet(),
+            config,
+            Arc::new(JobControl::new()),
+        )
+    }
+
+    fn config(cooling: job::Cooling) -> job::OptimizerConfig {
+        job::OptimizerConfig {
+            algorithm: job::Algorithm::SimulatedAnnealing,
+            seed: Some(9),
+            annealing: job::AnnealingConfig {
+                initial_temperature: 1.,
+                final_temperature: 0.1,
+                cooling,
+                moves_per_temperature: 5,
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn cools_down() {
+        let mut geometric = annealing(config(job::Cooling::Geometric { factor: 0.5 }));
+        assert_eq!(geometric.temperature(), 1.);
+        geometric.generation = 2;
+        assert_eq!(geometric.temperature(), 0.25);
+        // 1, 0.5, 0.25 and 0.125 are above the final temperature of 0.1
+        assert_eq!(
+            annealing(config(job::Cooling::Geometric { factor: 0.5 })).count(),
+            4
+        );
+
+        let mut linear = annealing(config(job::Cooling::Linear { step: 0.2 }));
+        linear.generation = 3;
+        assert!((linear.temperature() - 0.4).abs() < 1e-9);
+
+        let mut logarithmic = annealing(config(job::Cooling::Logarithmic));
+        assert_eq!(logarithmic.temperature(), 1.);
+        logarithmic.generation = 10;
+        assert!(logarithmic.temperature() < 1.);
+    }
+
+    #[test]
+    fn stops_when_it_stops_improving() {
+        // logarithmic cooling doesn't get near the final temperature
+        let mut annealing = annealing(job::OptimizerConfig {
+            stall_limit: 3,
+            ..config(job::Cooling::Logarithmic)
+        });
+        while annealing.next().is_some() {}
+        assert!(annealing.generation < 100);
+        assert_eq!(annealing.last_improvement, 3);
+    }
+
+    #[test]
+    fn reports_the_best_solution_so_far() {
+        let run = || {
+            annealing(job::OptimizerConfig {
+                max_generations: Some(6),
+                stall_limit: 100,
+                ..config(job::Cooling::Logarithmic)
+            })
+            .collect::<Vec<_>>()
+        };
+        let results = run();
+        assert_eq!(results.len(), 6);
+        assert!(results
+            .windows(2)
+            .all(|pair| pair[1].fitness <= pair[0].fitness));
+        assert!(results.iter().all(|result| result.seed == 9));
+        assert!(results
+            .iter()
+            .all(|result| result.placements_and_location.len() == 4));
+        assert_eq!(
+            serde_json::to_string(&results).unwrap(),
+            serde_json::to_string(&run()).unwrap()
+        );
+    }
+}
@@ -0,0 +1,87 @@
+// inputs, parts and sheets shared by the tests
+
+use std::sync::Arc;
+
+use geo::Coord;
+
+use crate::job;
+use crate::nest_polygon::NestPolygon;
+use crate::nesting_runner::{NestPart, Rotations};
+use crate::nfp_cache::NFPCache;
+
+// quantity L shaped brackets that fit in a 30 by 30 square, at any quarter turn
+pub fn l_brackets(quantity: u32, allow_mirror: bool) -> Arc<NFPCache> {
+    let l_bracket = NestPolygon::new(vec![
+        Coord { x: 0., y: 0. },
+        Coord { x: 30., y: 0. },
+        Coord { x: 30., y: 10. },
+        Coord { x: 10., y: 10. },
+        Coord { x: 10., y: 30. },
+        Coord { x: 0., y: 30. },
+    ]);
+    Arc::new(NFPCache::new(vec![NestPart {
+        quantity,
+        area: 500.,
+        polygon: l_bracket,
+        rotations: Rotations::angles(&[0., 90., 180., 270.], allow_mirror),
+        allow_mirror,
+        grain: None,
+    }]))
+}
+
+// a long and narrow sheet that fits a single row of brackets
+pub fn long_sheet() -> Vec<job::Sheet> {
+    vec![sheet(50., 200., 1.)]
+}
+
+pub fn square_part(size: f64, quantity: u32) -> NestPart {
+    NestPart {
+        quantity,
+        area: size * size,
+        polygon: NestPolygon::new(vec![
+            Coord { x: 0., y: 0. },
+            Coord { x: size, y: 0. },
+            Coord { x: size, y: size },
+            Coord { x: 0., y: size },
+        ]),
+        rotations: Rotations::angles(&[0.], false),
+        allow_mirror: false,
+        grain: None,
+    }
+}
+
+pub fn sheet(width: f32, length: f32, cost: f32) -> job::Sheet {
+    job::Sheet {
+        length,
+        width,
+        cost,
+        grain: None,
+    }
+}
+
+// a job with two 30 by 10 rectangles on a sheet that is 20 long and 40 wide
+pub fn input(rotations: Vec<f64>) -> job::Input {
+    job::Input {
+        nesting_job_ulid: "01EYQZJZJZJZJZJZJZJZJZJZJZ".to_owned(),
+        parts: vec![job::Part {
+            quantity: 2,
+            contour: vec![
+                Coord { x: 0., y: 0. },
+                Coord { x: 30., y: 0. },
+                Coord { x: 30., y: 10. },
+                Coord { x: 0., y: 10. },
+            ],
+            holes: vec![],
+            rotations,
+            rotation_range: None,
+            rotation_steps: None,
+            allow_mirror: false,
+            grain: None,
+        }],
+        sheets: vec![sheet(40., 20., 1.)],
+        tool_diameter: 0.,
+        timeout: 60_000,
+        offset_join_style: None,
+        optimizer: None,
+    }
+}
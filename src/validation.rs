@@ -0,0 +1,476 @@
+// checks the input of a job before it's queued, so invalid input is reported to the client instead
+// of making the nesting panic
+
+use geo::Coord;
+
+use crate::job;
+use crate::nest_polygon::{segment_intersection, NestPolygon, EPSILON};
+use crate::optimizer;
+
+pub fn validate(input: &job::Input) -> Result<(), job::Error> {
+    if input.parts.is_empty() {
+        return Err(invalid_input("there are no parts".to_owned()));
+    }
+    if input.sheets.is_empty() {
+        return Err(invalid_input("there are no sheets".to_owned()));
+    }
+    if !(0. ..).contains(&input.tool_diameter) {
+        return Err(invalid_input(format!(
+            "tool_diameter is {}, it can't be negative",
+            input.tool_diameter
+        )));
+    }
+    if input.timeout < 0 {
+        return Err(invalid_input(format!(
+            "timeout is {}, it can't be negative",
+            input.timeout
+        )));
+    }
+
+    if let Some(optimizer) = &input.optimizer {
+        validate_optimizer(optimizer)
+            .map_err(|problem| invalid_input(format!("the optimizer {}", problem)))?;
+    }
+
+    for (part_index, part) in input.parts.iter().enumerate() {
+        if part.quantity < 1 {
+            return Err(invalid_input(format!(
+                "part {} has a quantity of {}, it needs at least 1",
+                part_index, part.quantity
+            )));
+        }
+        validate_rotations(part)
+            .map_err(|problem| invalid_input(format!("part {} {}", part_index, problem)))?;
+        validate_ring(&part.contour).map_err(|problem| {
+            invalid_input(format!("the contour of part {} {}", part_index, problem))
+        })?;
+        for (hole_index, hole) in part.holes.iter().enumerate() {
+            validate_ring(hole).map_err(|problem| {
+                invalid_input(format!(
+                    "hole {} of part {} {}",
+                    hole_index, part_index, problem
+                ))
+            })?;
+        }
+        validate_holes(part).map_err(|problem| {
+            invalid_input(format!(
+                "hole {} of part {} {}",
+                problem.0, part_index, problem.1
+            ))
+        })?;
+    }
+
+    for (sheet_index, sheet) in input.sheets.iter().enumerate() {
+        if !(sheet.length > 0. && sheet.width > 0.) {
+            return Err(invalid_input(format!(
+                "sheet {} is {} long and {} wide, both have to be positive",
+                sheet_index, sheet.length, sheet.width
+            )));
+        }
+        // the fitness of a packing is the cost of its sheets, so free sheets can't be ranked
+        if sheet.cost.is_nan() || sheet.cost <= 0. {
+            return Err(invalid_input(format!(
+                "sheet {} costs {}, it has to be positive",
+                sheet_index, sheet.cost
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+fn validate_rotations(part: &job::Part) -> Result<(), &'static str> {
+    let given = [
+        !part.rotations.is_empty(),
+        part.rotation_range.is_some(),
+        part.rotation_steps.is_some(),
+    ];
+    match given.iter().filter(|&&given| given).count() {
+        0 => return Err("has no rotations"),
+        1 => {}
+        _ => return Err("can only have one of rotations, rotation_range and rotation_steps"),
+    }
+
+    if part.rotations.iter().any(|angle| !angle.is_finite()) {
+        return Err("has a rotation that isn't a finite number");
+    }
+    if let Some((from, to)) = part.rotation_range {
+        if !(from.is_finite() && to.is_finite() && from <= to) {
+            return Err("has a rotation_range that doesn't go from a lower to a higher angle");
+        }
+    }
+    if part.grain.is_some_and(|grain| !grain.is_finite()) {
+        return Err("has a grain that isn't a finite number");
+    }
+    if part.rotation_steps == Some(0) {
+        return Err("has 0 rotation_steps");
+    }
+    Ok(())
+}
+
+fn validate_optimizer(optimizer: &job::OptimizerConfig) -> Result<(), &'static str> {
+    // two parents are needed for a child
+    if optimizer.population_size < 2 {
+        return Err("needs a population_size of at least 2");
+    }
+    let rates = &optimizer.mutation;
+    let rates = [
+        rates.swap,
+        rates.reversal,
+        rates.insertion,
+        rates.scramble,
+        rates.rotation,
+        rates.mirror,
+    ];
+    if rates.iter().any(|rate| !(0. ..=1.).contains(rate)) {
+        return Err("needs mutation rates from 0 to 1");
+    }
+    if optimizer.elite_count >= optimizer.population_size {
+        return Err("needs an elite_count below the population_size");
+    }
+    match optimizer.selection {
+        job::Selection::LinearRank { pressure } if !(1. ..=2.).contains(&pressure) => {
+            return Err("needs a selection pressure from 1 to 2");
+        }
+        job::Selection::Tournament { size: 0 } => {
+            return Err("needs a tournament size of at least 1");
+        }
+        _ => {}
+    }
+    if optimizer.max_generations == Some(0) {
+        return Err("needs max_generations of at least 1");
+    }
+    if optimizer.stall_limit < 1 {
+        return Err("needs a stall_limit of at least 1");
+    }
+    if optimizer
+        .seed
+        .is_some_and(|seed| seed > optimizer::MAX_SEED)
+    {
+        return Err("needs a seed of at most 2^53 - 1");
+    }
+    if optimizer.islands < 1 {
+        return Err("needs at least 1 island");
+    }
+    if optimizer.migration_interval < 1 {
+        return Err("needs a migration_interval of at least 1");
+    }
+    if optimizer.algorithm == job::Algorithm::SimulatedAnnealing
+        && rates.iter().all(|&rate| rate == 0.)
+    {
+        return Err("needs a mutation rate above 0 for the moves of simulated annealing");
+    }
+    validate_annealing(&optimizer.annealing)
+}
+
+fn validate_annealing(annealing: &job::AnnealingConfig) -> Result<(), &'static str> {
+    if !(annealing.initial_temperature.is_finite() && annealing.initial_temperature > 0.) {
+        return Err("needs a positive initial_temperature");
+    }
+    if !(annealing.final_temperature > 0.
+        && annealing.final_temperature < annealing.initial_temperature)
+    {
+        return Err("needs a final_temperature from 0 to the initial_temperature");
+    }
+    match annealing.cooling {
+        job::Cooling::Geometric { factor } if !(factor > 0. && factor < 1.) => {
+            return Err("needs a cooling factor between 0 and 1");
+        }
+        job::Cooling::Linear { step } if !(step.is_finite() && step > 0.) => {
+            return Err("needs a positive cooling step");
+        }
+        _ => {}
+    }
+    if annealing.moves_per_temperature < 1 {
+        return Err("needs moves_per_temperature of at least 1");
+    }
+    Ok(())
+}
+
+fn invalid_input(message: String) -> job::Error {
+    job::Error {
+        error_type: job::ErrorType::InvalidInput,
+        message,
+    }
+}
+
+// returns what's wrong with the ring, which may or may not repeat its first vertex at the end
+fn validate_ring(ring: &[Coord]) -> Result<(), &'static str> {
+    if ring.iter().any(|c| !c.x.is_finite() || !c.y.is_finite()) {
+        return Err("has a vertex that isn't a finite number");
+    }
+
+    let edges = edges(ring);
+    if edges.len() < 3 {
+        return Err("has fewer than 3 vertices");
+    }
+
+    // edges that aren't next to each other must not touch, and edges next to each other must not
+    // double back over each other
+    for (i, &(a1, a2)) in edges.iter().enumerate() {
+        for (j, &(b1, b2)) in edges.iter().enumerate().skip(i + 1) {
+            let adjacent = j == i + 1 || (i == 0 && j == edges.len() - 1);
+            let touches = match collinear_overlap(a1, a2, b1, b2) {
+                Some(overlap) if adjacent => overlap > EPSILON,
+                Some(_) => true,
+                None => !adjacent && segment_intersection(a1, a2, b1, b2).is_some(),
+            };
+            if touches {
+                return Err("intersects itself");
+            }
+        }
+    }
+    Ok(())
+}
+
+// returns the index of the first hole that isn't strictly inside the contour or that overlaps an
+// earlier hole, with the problem. The rings are valid, see validate_ring.
+fn validate_holes(part: &job::Part) -> Result<(), (usize, String)> {
+    let contour = NestPolygon::new(part.contour.clone());
+    let polygons: Vec<_> = part.holes.iter().cloned().map(NestPolygon::new).collect();
+    for (hole_index, hole) in part.holes.iter().enumerate() {
+        if !hole.iter().all(|&c| contour.contains_strictly(c)) || rings_touch(hole, &part.contour) {
+            return Err((hole_index, "isn't inside the contour".to_owned()));
+        }
+
+        // one hole may lie inside the other without their edges touching
+        for (other_index, other) in part.holes[..hole_index].iter().enumerate() {
+            if rings_touch(hole, other)
+                || other
+                    .iter()
+                    .any(|&c| polygons[hole_index].contains_strictly(c))
+                || hole
+                    .iter()
+                    .any(|&c| polygons[other_index].contains_strictly(c))
+            {
+                return Err((hole_index, format!("overlaps hole {}", other_index)));
+            }
+        }
+    }
+    Ok(())
+}
+
+// the edges of the ring without repeated vertices
+fn edges(ring: &[Coord]) -> Vec<(Coord, Coord)> {
+    let mut vertices = ring.to_vec();
+    vertices.dedup();
+    if vertices.len() > 1 && vertices.first() == vertices.last() {
+        vertices.pop();
+    }
+    if vertices.len() < 3 {
+        return vec![];
+    }
+    (0..vertices.len())
+        .map(|i| (vertices[i], vertices[(i + 1) % vertices.len()]))
+        .collect()
+}
+
+fn rings_touch(a: &[Coord], b: &[Coord]) -> bool {
+    let b = edges(b);
+    edges(a).iter().any(|&(a1, a2)| {
+        b.iter().any(|&(b1, b2)| {
+            collinear_overlap(a1, a2, b1, b2).is_some()
+                || segment_intersection(a1, a2, b1, b2).is_some()
+        })
+    })
+}
+
+// returns the length that segments a1-a2 and b1-b2 share if they lie on the same line and touch,
+// segment_intersection ignores them
+fn collinear_overlap(a1: Coord, a2: Coord, b1: Coord, b2: Coord) -> Option<f64> {
+    let r = a2 - a1;
+    let length = r.x.hypot(r.y);
+    let distance = |c: Coord| ((c - a1).x * r.y - (c - a1).y * r.x).abs() / length;
+    if distance(b1) > EPSILON || distance(b2) > EPSILON {
+        return None;
+    }
+    let along = |c: Coord| ((c - a1).x * r.x + (c - a1).y * r.y) / length;
+    let (b_from, b_to) = (along(b1).min(along(b2)), along(b1).max(along(b2)));
+    let overlap = b_to.min(length) - b_from.max(0.);
+    (overlap >= -EPSILON).then_some(overlap.max(0.))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures;
+
+    fn input() -> job::Input {
+        test_fixtures::input(vec![0.])
+    }
+
+    fn message(input: &job::Input) -> String {
+        let error = validate(input).err().unwrap();
+        assert_eq!(error.error_type, job::ErrorType::InvalidInput);
+        error.message
+    }
+
+    #[test]
+    fn accepts_valid_input() {
+        assert!(validate(&input()).is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_parts() {
+        let mut empty = input();
+        empty.parts[0].contour = vec![];
+        assert_eq!(
+            message(&empty),
+            "the contour of part 0 has fewer than 3 vertices"
+        );
+
+        let mut line = input();
+        line.parts[0].contour.truncate(2);
+        assert_eq!(
+            message(&line),
+            "the contour of part 0 has fewer than 3 vertices"
+        );
+
+        let mut bow_tie = input();
+        bow_tie.parts[0].contour.swap(1, 2);
+        assert_eq!(message(&bow_tie), "the contour of part 0 intersects itself");
+
+        let mut hole = input();
+        hole.parts[0].holes = vec![vec![Coord { x: 1., y: 1. }, Coord { x: 2., y: 2. }]];
+        assert_eq!(message(&hole), "hole 0 of part 0 has fewer than 3 vertices");
+
+        let square = |x: f64, y: f64, size: f64| {
+            vec![
+                Coord { x, y },
+                Coord { x: x + size, y },
+                Coord {
+                    x: x + size,
+                    y: y + size,
+                },
+                Coord { x, y: y + size },
+            ]
+        };
+        let mut holes = input();
+        holes.parts[0].holes = vec![square(1., 1., 3.), square(5., 5., 3.)];
+        assert!(validate(&holes).is_ok());
+        holes.parts[0].holes[1] = square(8., 8., 4.);
+        assert_eq!(message(&holes), "hole 1 of part 0 isn't inside the contour");
+        holes.parts[0].holes[1] = square(20., 20., 2.);
+        assert_eq!(message(&holes), "hole 1 of part 0 isn't inside the contour");
+        holes.parts[0].holes[1] = square(0., 2., 2.);
+        assert_eq!(message(&holes), "hole 1 of part 0 isn't inside the contour");
+        holes.parts[0].holes[1] = square(3., 3., 3.);
+        assert_eq!(message(&holes), "hole 1 of part 0 overlaps hole 0");
+        holes.parts[0].holes[1] = square(2., 2., 1.);
+        assert_eq!(message(&holes), "hole 1 of part 0 overlaps hole 0");
+        holes.parts[0].holes[1] = square(4., 1., 3.);
+        assert_eq!(message(&holes), "hole 1 of part 0 overlaps hole 0");
+
+        // parallel edges that lie on top of each other
+        let mut folded = input();
+        folded.parts[0].contour = vec![
+            Coord { x: 0., y: 0. },
+            Coord { x: 10., y: 0. },
+            Coord { x: 10., y: 5. },
+            Coord { x: 5., y: 0. },
+            Coord { x: 2., y: 0. },
+            Coord { x: 0., y: 5. },
+        ];
+        assert_eq!(message(&folded), "the contour of part 0 intersects itself");
+        folded.parts[0].contour = vec![
+            Coord { x: 0., y: 0. },
+            Coord { x: 10., y: 0. },
+            Coord { x: 5., y: 0. },
+            Coord { x: 5., y: 5. },
+        ];
+        assert_eq!(message(&folded), "the contour of part 0 intersects itself");
+
+        let mut quantity = input();
+        quantity.parts[0].quantity = 0;
+        assert_eq!(
+            message(&quantity),
+            "part 0 has a quantity of 0, it needs at least 1"
+        );
+
+        let mut rotations = input();
+        rotations.parts[0].rotations = vec![];
+        assert_eq!(message(&rotations), "part 0 has no rotations");
+
+        let mut range = input();
+        range.parts[0].rotation_range = Some((0., 90.));
+        assert_eq!(
+            message(&range),
+            "part 0 can only have one of rotations, rotation_range and rotation_steps"
+        );
+        range.parts[0].rotations = vec![];
+        range.parts[0].rotation_range = Some((90., 0.));
+        assert_eq!(
+            message(&range),
+            "part 0 has a rotation_range that doesn't go from a lower to a higher angle"
+        );
+
+        let mut parts = input();
+        parts.parts = vec![];
+        assert_eq!(message(&parts), "there are no parts");
+    }
+
+    #[test]
+    fn rejects_invalid_sheets_and_settings() {
+        let mut width = input();
+        width.sheets[0].width = -5.;
+        assert_eq!(
+            message(&width),
+            "sheet 0 is 20 long and -5 wide, both have to be positive"
+        );
+
+        let mut cost = input();
+        cost.sheets[0].cost = 0.;
+        assert_eq!(message(&cost), "sheet 0 costs 0, it has to be positive");
+
+        let mut optimizer = input();
+        optimizer.optimizer = Some(job::OptimizerConfig {
+            elite_count: 10,
+            ..Default::default()
+        });
+        assert_eq!(
+            message(&optimizer),
+            "the optimizer needs an elite_count below the population_size"
+        );
+
+        optimizer.optimizer = Some(job::OptimizerConfig {
+            islands: 0,
+            ..Default::default()
+        });
+        assert_eq!(message(&optimizer), "the optimizer needs at least 1 island");
+        optimizer.optimizer = Some(job::OptimizerConfig {
+            seed: Some(u64::MAX),
+            ..Default::default()
+        });
+        assert_eq!(
+            message(&optimizer),
+            "the optimizer needs a seed of at most 2^53 - 1"
+        );
+        optimizer.optimizer = Some(job::OptimizerConfig {
+            algorithm: job::Algorithm::SimulatedAnnealing,
+            annealing: job::AnnealingConfig {
+                cooling: job::Cooling::Geometric { factor: 1. },
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        assert_eq!(
+            message(&optimizer),
+            "the optimizer needs a cooling factor between 0 and 1"
+        );
+
+        let mut sheets = input();
+        sheets.sheets = vec![];
+        assert_eq!(message(&sheets), "there are no sheets");
+
+        let mut tool_diameter = input();
+        tool_diameter.tool_diameter = -1.;
+        assert_eq!(
+            message(&tool_diameter),
+            "tool_diameter is -1, it can't be negative"
+        );
+
+        let mut timeout = input();
+        timeout.timeout = -1;
+        assert_eq!(message(&timeout), "timeout is -1, it can't be negative");
+    }
+}